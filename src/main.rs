@@ -1,15 +1,25 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Local};
-use clap::Parser;
-use colored::Colorize;
+use clap::{CommandFactory, Parser, ValueEnum};
+use colored::{Color, Colorize};
+use std::collections::{HashMap, HashSet};
+use std::io::{IsTerminal, Read};
+use std::path::{Component, Path, PathBuf};
 use std::time::SystemTime;
 
 #[cfg(target_os = "windows")]
 use std::os::windows::fs::MetadataExt;
 
-#[cfg(target_os = "unix")]
+#[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
+#[cfg(target_os = "macos")]
+use std::os::macos::fs::MetadataExt;
+
+// BSD file flag Finder uses to hide a file (chflags hidden)
+#[cfg(target_os = "macos")]
+const UF_HIDDEN: u32 = 0x8000;
+
 use walkdir::{self, WalkDir};
 
 #[derive(Parser)]
@@ -17,6 +27,14 @@ struct Arg {
     /// Paths of directories to list
     paths: Vec<String>,
 
+    #[arg(long)]
+    /// Also read paths to list from stdin, one per line (blank lines are skipped)
+    stdin: bool,
+
+    #[arg(long, requires = "stdin")]
+    /// Paths on stdin are separated by NUL instead of newlines, as from find -print0
+    null: bool,
+
     #[arg(short, long)]
     /// Show all files including hidden files
     all: bool,
@@ -25,18 +43,38 @@ struct Arg {
     /// List directories recursively
     recursive: bool,
 
-    #[arg(short = 't', long)]
-    /// Sort files by modification time
+    #[arg(short = 't', long, conflicts_with_all = ["sort", "sort_by_size"])]
+    /// Sort files by time, newest first (same as --sort time; --time picks which time)
     sort_by_time: bool,
 
     #[arg(short = 'r', long)]
     /// Reverse the order of the sort
     reverse: bool,
 
-    #[arg(short = 'S', long)]
-    /// sort by size
+    #[arg(short = 'S', long, conflicts_with = "sort")]
+    /// sort by size (same as --sort size)
     sort_by_size: bool,
 
+    #[arg(long, value_enum, value_name = "KIND")]
+    /// What to sort by (default: name)
+    sort: Option<SortKey>,
+
+    #[arg(long, value_enum, value_name = "FIELD", default_value = "modified")]
+    /// Which time the long format shows and the time sorts use
+    time: TimeField,
+
+    #[arg(long, value_name = "SPEC", value_parser = parse_relevance_weights)]
+    /// Weights for --sort relevance, e.g. recency:1,dirs:2,size:0.25 (the defaults)
+    relevance_weights: Option<RelevanceWeights>,
+
+    #[arg(long)]
+    /// List directories before files, keeping the sort order within each group
+    group_directories_first: bool,
+
+    #[arg(short = 'v', long)]
+    /// Sort names naturally, so numbers in them compare by value (file2 before file10)
+    version_sort: bool,
+
     #[arg(short = 'l', long)]
     /// Long format listing
     long_format: bool,
@@ -44,444 +82,6481 @@ struct Arg {
     #[arg(short = 'H', long)]
     /// Human-readable sizes
     human_readable: bool,
+
+    #[arg(long)]
+    /// After each listing, print a footer like "3 directories, 12 files, 4.2M total"
+    summary: bool,
+
+    #[arg(long)]
+    /// Make names clickable file:// links (OSC 8) in terminals that support them; only when colors are on
+    hyperlink: bool,
+
+    #[arg(short = 'F', long)]
+    /// Mark names by type: / directory, * executable, @ symlink, | FIFO, = socket
+    classify: bool,
+
+    #[arg(long, value_name = "N")]
+    /// Stop printing entries once the output reaches N bytes
+    max_output_bytes: Option<usize>,
+
+    #[arg(long, value_name = "SPEC", value_parser = parse_column_widths)]
+    /// Fixed long-format column widths, e.g. name:30,size:12,mtime:20
+    columns: Option<ColumnWidths>,
+
+    #[arg(long)]
+    /// Collect warnings and print them together after the listing
+    errors_at_end: bool,
+
+    #[arg(long, value_name = "NAME", requires = "recursive")]
+    /// Print the paths of every directory with this name (needs -R)
+    find_dirs: Option<String>,
+
+    #[arg(long, requires = "recursive")]
+    /// Draw recursive listings as a tree with ├── and └── connectors (needs -R)
+    tree: bool,
+
+    #[arg(long, value_name = "N", requires = "recursive", value_parser = parse_max_depth)]
+    /// With -R or --tree, only go N levels deep (1 lists just the directory itself)
+    max_depth: Option<usize>,
+
+    #[arg(long, requires = "recursive")]
+    /// Start each line with the entry's depth below the listed directory (needs -R)
+    show_depth: bool,
+
+    #[arg(long, requires = "recursive")]
+    /// Print the tree as JSON in the same shape as `tree -J` (needs -R)
+    tree_json: bool,
+
+    #[arg(long, value_name = "DIR")]
+    /// Write each path's listing to its own file in DIR (e.g. DIR/src.txt) instead of stdout
+    split_output: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE", requires = "recursive")]
+    /// Write a sorted manifest of every file (size, mtime, path) to FILE instead of listing (needs -R)
+    manifest: Option<PathBuf>,
+
+    #[arg(long, requires = "manifest")]
+    /// Add a CRC-32 checksum of each regular file to the --manifest
+    checksum: bool,
+
+    #[arg(long)]
+    /// Quote names for pasting into a POSIX shell
+    shell_quote: bool,
+
+    #[arg(long)]
+    /// Print nothing, exit 0 if any entry would be listed and 1 otherwise
+    exists: bool,
+
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_template)]
+    /// Custom per-entry format, e.g. '{size:>10} {mtime} {name}'
+    /// (fields: name, path, size, mtime, perms, owner, inode)
+    format_string: Option<Template>,
+
+    #[arg(long, value_name = "K", conflicts_with = "recursive")]
+    /// Show each directory with up to K of its entries indented beneath it
+    overview: Option<usize>,
+
+    #[arg(long)]
+    /// Ignore leading articles ("The", "A", "An") when sorting by name
+    ignore_articles: bool,
+
+    #[arg(
+        long,
+        value_name = "WORDS",
+        value_delimiter = ',',
+        default_value = "the,a,an"
+    )]
+    /// Comma-separated articles skipped by --ignore-articles
+    articles: Vec<String>,
+
+    #[arg(long)]
+    /// Print the listing as a Graphviz DOT graph (use with -R for the whole tree)
+    dot: bool,
+
+    #[arg(long, value_name = "N")]
+    /// Warn on stderr about any directory holding more than N entries
+    warn_large_dir: Option<usize>,
+
+    #[arg(long, value_enum)]
+    /// Layout for the short format, or json for scripts [default: grid on a terminal, single-column when piped]
+    format: Option<OutputFormat>,
+
+    #[arg(long)]
+    /// With --format json, print the bare array (or path-keyed object) without the
+    /// {"version", "entries"} envelope
+    json_bare: bool,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    /// Skip files larger than SIZE (e.g. 500K, 10M, 1.5G); directories are kept
+    exclude_larger_than: Option<u64>,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    /// Only show files of at least SIZE (e.g. 500K, 10M); directories are always shown
+    min_size: Option<u64>,
+
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    /// Only show files of at most SIZE (e.g. 500K, 10M); directories are always shown
+    max_size: Option<u64>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    /// Only show entries modified within DURATION of now (e.g. 30m, 12h, 7d, 2w)
+    newer_than: Option<std::time::Duration>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    /// Only show entries last modified more than DURATION ago (e.g. 30m, 12h, 7d, 2w)
+    older_than: Option<std::time::Duration>,
+
+    #[arg(long)]
+    /// Resolve path arguments against the filesystem (symlinks, `..`) instead of just tidying them
+    canonicalize: bool,
+
+    #[arg(long)]
+    /// List each file only once when path arguments overlap
+    dedup: bool,
+
+    #[arg(long)]
+    /// Exit with an error if any entry was skipped because of a permission error
+    strict: bool,
+
+    #[arg(long)]
+    /// Exit with an error if a symlink loop was found and skipped (only happens with --walk logical)
+    error_on_loop: bool,
+
+    #[arg(long)]
+    /// Show Windows attributes as a short flag string like R-SA (Windows only)
+    compact_attrs: bool,
+
+    #[arg(long, value_name = "CMD")]
+    /// Sort by the output of CMD, run once per entry with the name as its last argument
+    sort_key_exec: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value = "command-line-logical"
+    )]
+    /// How to treat symlinks: never follow (physical), always follow (logical), or only
+    /// follow symlinks given as path arguments (command-line-logical), like find -P/-L/-H
+    walk: WalkMode,
+
+    #[arg(long, conflicts_with = "walk")]
+    /// Follow symlinks to directories while walking, but list symlinks to files as links
+    follow_only_dirs: bool,
+
+    #[arg(long, value_name = "GLOB", value_parser = parse_glob)]
+    /// Hide entries whose name matches GLOB, e.g. '*.o' or 'target/' (repeatable)
+    ignore: Vec<Glob>,
+
+    #[arg(long)]
+    /// Don't apply the patterns in the global ignore file (~/.config/lister/ignore)
+    no_global_ignore: bool,
+
+    #[arg(long)]
+    /// Match --ignore globs without regard to case, so '*.jpg' also hides PHOTO.JPG
+    ignore_case: bool,
+
+    #[arg(long, value_name = "BASE")]
+    /// Name entries by their path relative to BASE, in the short and the long format
+    relative_to: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Leave out device files, FIFOs (named pipes) and sockets
+    no_special: bool,
+
+    #[arg(long)]
+    /// Leave out whatever Git ignores (.gitignore and .ignore files, .git/info/exclude, global excludes)
+    gitignore: bool,
+
+    #[arg(long)]
+    /// Don't descend into hidden directories like .git or .venv when recursing
+    skip_hidden_dirs: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ".nomedia"
+    )]
+    /// Leave out directories containing a marker file NAME (default .nomedia), with everything
+    /// in them; a NAME must be attached as --respect-markers=NAME
+    respect_markers: Option<String>,
+
+    #[arg(long, requires = "long_format")]
+    /// Print a title row above the long-format columns
+    header: bool,
+
+    #[arg(long, requires = "long_format")]
+    /// Show file capabilities like getcap, e.g. "cap_net_raw=ep", after the owner (Linux only)
+    capabilities: bool,
+
+    #[arg(long, requires = "long_format")]
+    /// Add a column with how long ago each entry was modified, like "3 days ago"
+    relative_mtime: bool,
+
+    #[arg(long, requires = "long_format")]
+    /// Show sizes as exact bytes with the human-readable size after them, like "1048576 (1.0M)"
+    size_both: bool,
+
+    #[arg(long, value_name = "N", requires = "long_format")]
+    /// Cut owner and group names longer than N characters, ending them with "…"
+    max_owner_width: Option<usize>,
+
+    #[arg(long, value_enum, value_name = "WHEN", default_value = "auto")]
+    /// When to use colors: auto (only on a terminal, and never if NO_COLOR is set), always or never
+    color: ColorWhen,
+}
+
+// Choices for --time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum TimeField {
+    /// Last modification (mtime)
+    Modified,
+    /// Last access (atime); a noatime or relatime mount may not update it, and whatever
+    /// the system reports is shown
+    Accessed,
+    /// Creation (birth time), where the platform and filesystem record it
+    Created,
+}
+
+// Choices for --color
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ColorWhen {
+    /// Color only when printing to a terminal and NO_COLOR is not set
+    Auto,
+    /// Always color, even when piped or with NO_COLOR set
+    Always,
+    /// Never color
+    Never,
+}
+
+// Symlink-following policies for the walk, matching find's -P, -L and -H
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum WalkMode {
+    /// Never follow symlinks
+    Physical,
+    /// Follow every symlink (loops are reported and skipped)
+    Logical,
+    /// Follow symlinks named on the command line, but not ones found while walking
+    CommandLineLogical,
+}
+
+// Keys for --sort
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum SortKey {
+    /// Alphabetically, ignoring case
+    Name,
+    /// Newest first
+    Time,
+    /// Largest first
+    Size,
+    /// By file extension, then name
+    Extension,
+    /// By name length in characters, shortest first, then name
+    Length,
+    /// By owner name, then name (Unix only; elsewhere the same as name)
+    Owner,
+    /// By group name, then name (Unix only; elsewhere the same as name)
+    Group,
+    /// By hard link count, fewest first, then name (Unix only; elsewhere the same as name)
+    Links,
+    /// Leave entries in directory order
+    None,
+    /// By age bucket (today, yesterday, this week, ...), newest first
+    DateBucket,
+    /// By a size written in the name, like backup-500MB.tar, smallest first
+    EmbeddedSize,
+    /// By a relevance score from recency, type and size, highest first (see --relevance-weights)
+    Relevance,
+}
+
+// One piece of a name for --version-sort; numbers sort before text, like digits before letters
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum NaturalChunk {
+    Number(usize, String), // (significant digit count, digits without leading zeros)
+    Text(String),
+}
+
+// Coarse modification-time ranges for --sort date-bucket, newest first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum DateBucket {
+    Today,
+    Yesterday,
+    ThisWeek,
+    ThisMonth,
+    ThisYear,
+    Older,
+}
+
+// Layouts for the short (non-long) listing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Names in columns sized to the terminal, like `ls -C`
+    Grid,
+    /// One name per line, like `ls -1`
+    SingleColumn,
+    /// All names on one line separated by spaces
+    Line,
+    /// A JSON array of entries (an object of arrays keyed by path when given several paths)
+    Json,
 }
 
 fn main() -> Result<()> {
     // Parse command-line arguments
-    let arg = Arg::parse();
-
-    // Collect the provided paths into a vector
-    let paths: &[String] = &arg.paths;
-
-    let separator = if arg.long_format { "\n" } else { " " };
-
-    // If there are multiple arguments, list contents for each specified path
-    if !paths.is_empty() {
-        for path in paths.iter() {
-            println!("{}:", path.green());
-            let entries = collect_entries(path, &arg)
-                .with_context(|| format!("Failed to read directory: {}", path))?; // Collect entries for the given path
-            let display_entries = should_display(entries, &arg); // filter entries based on visibility
-            let sorted_entries = sort_entries(display_entries, &arg); // sort entries based on criteria
-            let formatted_entries = format_entries(sorted_entries, &arg); // format entries for display
-            println!("{}", formatted_entries.join(separator)); // Print formatted entries
-            println!(); // Print a newline for separation between different paths
+    let mut arg = Arg::parse();
+    if let Err(message) = check_size_range(&arg) {
+        Arg::command()
+            .error(clap::error::ErrorKind::ArgumentConflict, message)
+            .exit();
+    }
+    // Patterns from the global ignore file apply on top of any --ignore
+    if !arg.no_global_ignore
+        && let Some(file) = global_ignore_file()
+    {
+        arg.ignore.extend(read_glob_file(&file));
+    }
+    if arg.ignore_case {
+        for glob in &mut arg.ignore {
+            glob.ignore_case = true;
         }
-        // If no arguments are provided, list contents of the current directory
-    } else {
-        let entries = collect_entries(".", &arg).context("failed to read current directory")?;
-        let display_entries = should_display(entries, &arg);
-        let sorted_entries = sort_entries(display_entries, &arg);
-        let formatted_entries = format_entries(sorted_entries, &arg);
-        println!("{}", formatted_entries.join(separator));
     }
-    Ok(())
-}
+    // On a recursive walk, the first Ctrl-C stops the walk and lists what was found so far
+    if arg.recursive {
+        install_interrupt_handler();
+    }
 
-// Function to collect entries from a directory based on the provided path and arguments(like recursive)
-fn collect_entries(path: &str, arg: &Arg) -> Result<Vec<Entry>> {
-    let mut results = Vec::new();
+    // Warnings from the walk, printed right away or held back for --errors-at-end
+    let mut warnings = Warnings::default();
 
-    // walker = interator over directory entries recursively or non-recursively based on arg.recursive
-    let walker = if arg.recursive {
-        WalkDir::new(path).min_depth(1)
-    } else {
-        WalkDir::new(path).max_depth(1).min_depth(1)
-    };
+    // Collect the provided paths into a vector, cleaned up for headers and full-path output
+    // A path that can't be resolved is reported and left out; the others are still listed
+    let mut paths: Vec<String> = Vec::new();
+    for path in &arg.paths {
+        match resolve_path(path, &arg) {
+            Ok(path) => paths.push(path),
+            Err(e) => warnings.fail_path(e),
+        }
+    }
+    if arg.stdin {
+        let mut input = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut input)
+            .context("Failed to read paths from stdin")?;
+        // A path that doesn't exist is only a warning, so one bad line doesn't stop the batch
+        for path in stdin_paths(&String::from_utf8_lossy(&input), arg.null) {
+            if Path::new(&path).exists() {
+                match resolve_path(&path, &arg) {
+                    Ok(path) => paths.push(path),
+                    Err(e) => warnings.fail_path(e),
+                }
+            } else {
+                warnings.failed_paths += 1;
+                warnings.push(format!("{}: No such file or directory", path));
+            }
+        }
+        flush_warnings(&mut warnings, &arg);
+    }
+    // With --stdin, only the paths given are listed, never the current directory
+    let has_paths = !paths.is_empty() || arg.stdin;
+    let current_dir = resolve_path(".", &arg)?;
 
-    for entry in walker {
-        match entry {
-            Ok(dir_entry) => {
-                let meta_data = dir_entry.metadata().with_context(|| {
-                    format!("Failed to read metadata for {}", dir_entry.path().display())
-                })?;
+    // --exists only reports through the exit code, stopping at the first match
+    if arg.exists {
+        let found = if !has_paths {
+            has_visible_entry(&current_dir, &arg)
+        } else {
+            paths.iter().any(|path| has_visible_entry(path, &arg))
+        };
+        std::process::exit(if found { 0 } else { 1 });
+    }
 
-                let attribute: u32;
+    // Listings written to --split-output files are laid out and colored as if piped
+    let to_terminal = std::io::stdout().is_terminal() && arg.split_output.is_none();
+    let format = output_format(&arg, to_terminal);
 
-                #[cfg(target_os = "unix")]
-                {
-                    attribute = meta_data.permissions().mode();
+    // Every colored path (headers, names, permissions) checks colored's switch, so set it once here
+    colored::control::set_override(color_enabled(
+        arg.color,
+        std::env::var_os("NO_COLOR").as_deref(),
+        to_terminal,
+    ));
+
+    // Real paths already listed, so --dedup can skip them under a later overlapping argument
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    if arg.recursive && !arg.dedup {
+        warnings.messages.extend(overlap_warnings(&paths));
+    }
+
+    // Paths for the modes that cover every argument at once
+    let roots = if !has_paths {
+        std::slice::from_ref(&current_dir)
+    } else {
+        &paths[..]
+    };
+
+    if let Some(manifest) = &arg.manifest {
+        let mut files = Vec::new();
+        for root in roots {
+            let entries = match collect_entries(root, &arg, &mut warnings) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warnings.fail_path(e.context(format!("Failed to read directory: {}", root)));
+                    continue;
                 }
-                #[cfg(target_os = "windows")]
-                {
-                    attribute = meta_data.file_attributes();
+            };
+            files.extend(
+                should_display(entries, &arg)
+                    .into_iter()
+                    .filter(|entry| entry.kind != FileKind::Directory),
+            );
+        }
+        let contents = manifest_lines(files, arg.checksum, &mut warnings);
+        flush_warnings(&mut warnings, &arg);
+        if let Err(e) = std::fs::write(manifest, contents) {
+            warnings.fail_path(
+                anyhow::Error::new(e)
+                    .context(format!("Failed to write manifest: {}", manifest.display())),
+            );
+        }
+    } else if format == OutputFormat::Json {
+        let mut listings = Vec::new();
+        for root in roots {
+            let entries = match collect_entries(root, &arg, &mut warnings) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warnings.fail_path(e.context(format!("Failed to read directory: {}", root)));
+                    continue;
                 }
-                #[cfg(not(any(target_os = "unix", target_os = "windows")))]
-                {
-                    attribute = 0;
+            };
+            let entries = sort_entries(should_display(entries, &arg), &arg, &mut warnings);
+            listings.push((root.as_str(), entries));
+        }
+        flush_warnings(&mut warnings, &arg);
+        let listing = json_listing(&listings, roots.len() > 1);
+        if arg.json_bare {
+            println!("{}", listing);
+        } else {
+            println!("{}", json_envelope(&listing));
+        }
+    } else if arg.tree_json {
+        let mut trees = Vec::new();
+        for root in roots {
+            let entries = match collect_entries(root, &arg, &mut warnings) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warnings.fail_path(e.context(format!("Failed to read directory: {}", root)));
+                    continue;
                 }
-
-                let entry_data = Entry {
-                    name: if dir_entry.file_type().is_dir() {
-                        format!("{}/", dir_entry.file_name().to_string_lossy())
-                    } else {
-                        format!("{}", dir_entry.file_name().to_string_lossy())
-                    },
-                    modified: meta_data.modified().with_context(|| {
-                        format!(
-                            "Failed to get modified time for {}",
-                            dir_entry.path().display()
-                        )
-                    })?,
-                    size: meta_data.len(),
-                    attribute,
-                };
-
-                results.push(entry_data);
+            };
+            trees.push((
+                root.as_str(),
+                sort_entries(should_display(entries, &arg), &arg, &mut warnings),
+            ));
+        }
+        flush_warnings(&mut warnings, &arg);
+        println!("{}", tree_json(&trees));
+    } else if let Some(dir) = &arg.split_output {
+        // File names already written this run, so colliding paths don't overwrite each other
+        let mut taken = HashSet::new();
+        for root in roots {
+            let written = write_split_listing(
+                dir,
+                root,
+                &arg,
+                format,
+                &mut seen,
+                &mut taken,
+                &mut warnings,
+            );
+            if let Err(e) = written {
+                warnings.fail_path(e.context(format!(
+                    "Failed to list {} into {}",
+                    root,
+                    dir.display()
+                )));
             }
-            Err(e) => {
-                eprintln!("Warning: {}", e);
+        }
+        // If there are multiple arguments, list contents for each specified path
+    } else if has_paths {
+        // What's left of --max-output-bytes, shared by every path so N paths can't print N times it
+        let mut budget = arg.max_output_bytes;
+        for (index, path) in paths.iter().enumerate() {
+            if budget == Some(0) {
+                println!(
+                    "... output truncated at {} bytes ({} more paths)",
+                    arg.max_output_bytes.unwrap_or(0),
+                    paths.len() - index
+                );
+                break;
+            }
+            // A path that can't be read is reported, and the rest are still listed
+            let output =
+                match path_listing(path, &arg, format, &mut seen, &mut warnings, &mut budget) {
+                    Ok(output) => output,
+                    Err(e) => {
+                        warnings
+                            .fail_path(e.context(format!("Failed to read directory: {}", path)));
+                        continue;
+                    }
+                };
+            println!("{}", output); // Print the header and formatted entries
+            println!(); // Print a newline for separation between different paths
+            if interrupted() {
+                break;
             }
         }
+        // If no arguments are provided, list contents of the current directory
+    } else {
+        let mut budget = arg.max_output_bytes;
+        match list_path(
+            &current_dir,
+            &arg,
+            format,
+            &mut seen,
+            &mut warnings,
+            &mut budget,
+        ) {
+            Ok(output) => println!("{}", output),
+            Err(e) => warnings.fail_path(e.context("failed to read current directory")),
+        }
     }
 
-    Ok(results)
+    if !warnings.messages.is_empty() {
+        eprint!("{}", warning_summary(&warnings.messages));
+    }
+    if let Some(error) = run_failure(&warnings, &arg) {
+        eprintln!("Error: {}", error);
+    }
+    if interrupted() {
+        eprintln!("Interrupted: the listing above is partial");
+        std::process::exit(130);
+    }
+    let code = exit_code(&warnings, &arg);
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
 }
 
-// Function to filter entries based on visibility (hidden or not)
-fn should_display(entries: Vec<Entry>, arg: &Arg) -> Vec<Entry> {
-    if arg.all {
-        entries
-    } else {
-        entries
-            .into_iter()
-            .filter(|entry| {
-                // Filter dot files on all platforms
-                let is_dot_file = entry.name.starts_with(".");
+// Set by the first Ctrl-C during a recursive walk; the walk stops at its next entry
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
 
-                #[cfg(target_os = "windows")]
-                let is_hidden = entry.attribute & 0x2 != 0; // Check HIDDEN attribute
+// Function to check whether Ctrl-C was pressed
+fn interrupted() -> bool {
+    INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst)
+}
 
-                #[cfg(not(target_os = "windows"))]
-                let is_hidden = false; // No additional hidden check on Unix
+// Function to record a Ctrl-C in `flag`, returning true when one was already recorded
+fn note_interrupt(flag: &std::sync::atomic::AtomicBool) -> bool {
+    flag.swap(true, std::sync::atomic::Ordering::SeqCst)
+}
 
-                !is_dot_file && !is_hidden
-            })
-            .collect()
+// Function to install the SIGINT handler: the first Ctrl-C sets INTERRUPTED, a second one
+// quits right away (Unix only; elsewhere Ctrl-C keeps its default behavior)
+fn install_interrupt_handler() {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_interrupt(_signal: i32) {
+            if note_interrupt(&INTERRUPTED) {
+                // SAFETY: _exit is async-signal-safe, unlike std::process::exit
+                unsafe { ffi::_exit(130) };
+            }
+        }
+        const SIGINT: i32 = 2;
+        // SAFETY: the handler only touches an atomic and calls _exit
+        unsafe { ffi::signal(SIGINT, on_interrupt) };
     }
 }
 
-// Function to sort entries based on the provided arguments
-fn sort_entries(mut entries: Vec<Entry>, arg: &Arg) -> Vec<Entry> {
-    if arg.sort_by_time {
-        entries.sort_by(|a, b| a.modified.cmp(&b.modified));
-        if !arg.reverse {
-            entries.reverse();
-        }
-    } else if arg.sort_by_size {
-        entries.sort_by(|a, b| a.size.cmp(&b.size));
-        if !arg.reverse {
-            entries.reverse();
-        }
-    } else {
-        // Default: sort alphabetically (case-insensitive)
-        entries.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-        if arg.reverse {
-            entries.reverse();
-        }
+// Function to write one path's listing to its own file in the --split-output directory
+// (created if needed) and return the file's path
+// A name already in `taken` gets a numeric suffix, e.g. src_bin-2.txt
+fn write_split_listing(
+    dir: &Path,
+    path: &str,
+    arg: &Arg,
+    format: OutputFormat,
+    seen: &mut HashSet<PathBuf>,
+    taken: &mut HashSet<String>,
+    warnings: &mut Warnings,
+) -> Result<PathBuf> {
+    // Each file is its own output, with the whole --max-output-bytes to itself
+    let output = list_path(
+        path,
+        arg,
+        format,
+        seen,
+        warnings,
+        &mut arg.max_output_bytes.clone(),
+    )?;
+    std::fs::create_dir_all(dir)?;
+    let name = split_output_name(path);
+    let mut unique = name.clone();
+    let mut n = 1;
+    while !taken.insert(unique.clone()) {
+        n += 1;
+        unique = format!("{}-{}.txt", name.trim_end_matches(".txt"), n);
     }
-    entries
+    let file = dir.join(unique);
+    std::fs::write(&file, format!("{}\n", output))?;
+    Ok(file)
 }
 
-// Function to format entries for display based on long_format and human_readable options
-fn format_entries(entries: Vec<Entry>, arg: &Arg) -> Vec<String> {
-    // taking each entry from the Vector and formatting it based on the long_format flag and human-readable size option
-    let formatted_entries = entries
-        .into_iter()
-        .map(|f| {
-            if arg.long_format {
-                let datetime: DateTime<Local> = f.modified.into();
-                let size_display = if arg.human_readable {
-                    format_size(f.size)
-                } else {
-                    format!("{}B", f.size)
-                };
-                let attributes = parse_attributes(f.attribute);
-                format!(
-                    "{:<20}  {:>10} size  modified: {:<15} attributes: {}",
-                    f.name,
-                    size_display,
-                    datetime.format("%b %d %H:%M"),
-                    attributes
-                )
+// Function to name a path's --split-output file: the path with its separators turned into
+// "_" and a ".txt" extension, so "src/bin" becomes "src_bin.txt" ("/" is root.txt and the
+// current directory current.txt)
+fn split_output_name(path: &str) -> String {
+    let name: String = path
+        .trim_start_matches(['/', '\\'])
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c == ':' {
+                '_'
             } else {
-                f.name.to_string()
+                c
             }
         })
         .collect();
+    let name = match name.as_str() {
+        "" => "root",
+        "." => "current",
+        name => name,
+    };
+    format!("{}.txt", name)
+}
 
-    formatted_entries
+// Function to pick the exit status like GNU ls: 2 when a path given couldn't be read at all,
+// 1 for lesser trouble (entries skipped because of I/O errors, --strict, --error-on-loop),
+// and 0 for a clean run
+fn exit_code(warnings: &Warnings, arg: &Arg) -> i32 {
+    if warnings.failed_paths > 0 {
+        2
+    } else if warnings.permission_denied + warnings.not_found + warnings.other_io > 0
+        || run_failure(warnings, arg).is_some()
+    {
+        1
+    } else {
+        0
+    }
 }
 
-// Function to format file sizes into human-readable strings
-fn format_size(bytes: u64) -> String {
-    const KB: u64 = 1024;
-    const MB: u64 = KB * 1024;
-    const GB: u64 = MB * 1024;
+// Function to decide whether a finished run should still fail: --strict after permission
+// errors, or --error-on-loop after symlink loops were skipped
+fn run_failure(warnings: &Warnings, arg: &Arg) -> Option<String> {
+    if arg.strict && warnings.permission_denied > 0 {
+        Some(format!(
+            "{} entries skipped because of permission errors",
+            warnings.permission_denied
+        ))
+    } else if arg.error_on_loop && warnings.loops > 0 {
+        Some(format!("{} symlink loops skipped", warnings.loops))
+    } else {
+        None
+    }
+}
 
-    if bytes >= GB {
-        format!("{:.1}G", bytes as f64 / GB as f64)
-    } else if bytes >= MB {
-        format!("{:.1}M", bytes as f64 / MB as f64)
-    } else if bytes >= KB {
-        format!("{:.1}K", bytes as f64 / KB as f64)
+// Function to split the --stdin input into paths, on newlines or with --null on NUL bytes,
+// skipping blank entries (a trailing "\r" from Windows line endings is dropped too)
+fn stdin_paths(input: &str, null: bool) -> Vec<String> {
+    let separator = if null { '\0' } else { '\n' };
+    input
+        .split(separator)
+        .map(|path| {
+            if null {
+                path
+            } else {
+                path.trim_end_matches('\r')
+            }
+        })
+        .filter(|path| !path.trim().is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Function to clean up a path argument: fold `.` and `..` logically, or with --canonicalize
+// ask the filesystem for the real absolute path
+fn resolve_path(path: &str, arg: &Arg) -> Result<String> {
+    if arg.canonicalize {
+        let canonical = std::fs::canonicalize(path)
+            .with_context(|| format!("Failed to canonicalize path: {}", path))?;
+        Ok(canonical.display().to_string())
     } else {
-        format!("{}B", bytes)
+        Ok(normalize_path(path).display().to_string())
     }
 }
-fn parse_attributes(attr: u32) -> String {
-    #[cfg(target_os = "windows")]
-    {
-        let mut attributes = Vec::new();
 
-        if attr & 0x1 != 0 {
-            attributes.push("READONLY");
-        }
-        if attr & 0x2 != 0 {
-            attributes.push("HIDDEN");
-        }
-        if attr & 0x4 != 0 {
-            attributes.push("SYSTEM");
-        }
-        if attr & 0x20 != 0 {
-            attributes.push("ARCHIVE");
+// Function to fold `.` and `..` components without touching the filesystem
+// `..` at the start of a relative path is kept, `..` above the root is dropped
+fn normalize_path(path: &str) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other.as_os_str()),
         }
+    }
+    if normalized.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        normalized
+    }
+}
 
-        if attributes.is_empty() {
-            String::from("NORMAL")
+// Function to list one of several path arguments under a "path:" header
+// The header and the blank line printed after the listing are taken out of `budget` too
+fn path_listing(
+    path: &str,
+    arg: &Arg,
+    format: OutputFormat,
+    seen: &mut HashSet<PathBuf>,
+    warnings: &mut Warnings,
+    budget: &mut Option<usize>,
+) -> Result<String> {
+    let header = if arg.dot {
+        String::new()
+    } else {
+        format!("{}:\n", path.green())
+    };
+    let framing = if arg.dot { 0 } else { path.len() + 2 } + "\n\n".len();
+    // Only charged once the path could be read, so a failed one doesn't use up the budget
+    let mut left = budget.map(|left| left.saturating_sub(framing));
+    let output = list_path(path, arg, format, seen, warnings, &mut left)?;
+    *budget = left;
+    Ok(format!("{}{}", header, output))
+}
+
+// Function to run the whole listing pipeline for one path and return the text to print
+// `budget` is what's left of --max-output-bytes, and is reduced by what this path prints
+fn list_path(
+    path: &str,
+    arg: &Arg,
+    format: OutputFormat,
+    seen: &mut HashSet<PathBuf>,
+    warnings: &mut Warnings,
+    budget: &mut Option<usize>,
+) -> Result<String> {
+    let mut entries = collect_entries(path, arg, warnings)?; // Collect entries for the given path
+    if arg.dedup {
+        entries = dedup_entries(entries, path, arg, seen); // skip files an earlier path already listed
+    }
+    let display_entries = should_display(entries, arg); // filter entries based on visibility
+    if let Some(name) = &arg.find_dirs {
+        flush_warnings(warnings, arg);
+        return Ok(find_dirs(&display_entries, name).join("\n"));
+    }
+    let sorted_entries = sort_entries(display_entries, arg, warnings); // sort entries based on criteria
+    let summary = arg.summary.then(|| summary_line(&sorted_entries, arg));
+    if arg.dot {
+        flush_warnings(warnings, arg);
+        return Ok(dot_graph(path, &sorted_entries));
+    }
+    let mut formatted_entries = match arg.overview {
+        Some(limit) => overview_lines(sorted_entries, limit, arg, warnings), // expand each directory a little
+        None if arg.tree => tree_lines(path, sorted_entries, arg),           // nest under parents
+        None => format_entries(sorted_entries, arg), // format entries for display
+    };
+    if arg.header && arg.format_string.is_none() {
+        let attributes_title = if cfg!(target_os = "windows") {
+            "ATTRIBUTES"
         } else {
-            attributes.join(", ")
+            "PERMISSIONS"
+        };
+        let header = long_format_line(
+            &LongColumns {
+                name: "NAME",
+                name_color: None,
+                link: None,
+                name_suffix: "",
+                target_color: None,
+                size: "SIZE",
+                size_bytes: None,
+                modified: match arg.time {
+                    TimeField::Modified => "MODIFIED",
+                    TimeField::Accessed => "ACCESSED",
+                    TimeField::Created => "CREATED",
+                },
+                attributes: attributes_title,
+                color_permissions: false,
+                age: arg.relative_mtime.then_some("AGE"),
+                capabilities: None,
+                owner: cfg!(unix).then_some(("OWNER", "GROUP")),
+            },
+            arg,
+        );
+        formatted_entries.insert(0, header);
+    }
+    let separator = if format == OutputFormat::Line {
+        " "
+    } else {
+        "\n"
+    };
+    flush_warnings(warnings, arg);
+    let output = if format == OutputFormat::Grid {
+        grid_output(&formatted_entries, terminal_width(), arg, budget)
+    } else {
+        // cap output size
+        limit_output_bytes(formatted_entries, separator, "entries", arg, budget).join(separator)
+    };
+    let Some(summary) = summary else {
+        return Ok(output);
+    };
+    // The summary counts toward the budget as well, and is left out when it doesn't fit
+    if let Some(left) = budget.as_mut() {
+        if *left <= summary.len() {
+            *left = 0;
+            return Ok(output);
         }
+        *left -= summary.len() + 1;
     }
+    Ok(format!("{}\n{}", output, summary))
+}
 
-    #[cfg(target_os = "unix")]
-    {
-        // Unix permissions (mode) - show as octal (e.g., 644, 755)
-        format!("{:o}", attr & 0o777)
+// Function to build the --summary footer for the listed entries (everything walked with -R)
+// Anything that isn't a directory counts as a file, and the total adds up the file sizes
+fn summary_line(entries: &[Entry], arg: &Arg) -> String {
+    let directories = entries
+        .iter()
+        .filter(|entry| entry.kind == FileKind::Directory)
+        .count();
+    let files = entries.len() - directories;
+    let total: u64 = entries
+        .iter()
+        .filter(|entry| entry.kind != FileKind::Directory)
+        .map(|entry| entry.size)
+        .sum();
+    let total = if arg.human_readable {
+        format_size(total)
+    } else {
+        format!("{}B", total)
+    };
+    let plural = |count: usize, one: &str, many: &str| {
+        format!("{} {}", count, if count == 1 { one } else { many })
+    };
+    format!(
+        "{}, {}, {} total",
+        plural(directories, "directory", "directories"),
+        plural(files, "file", "files"),
+        total
+    )
+}
+
+// Function to pick the layout: line-based modes (long format, templates, overview) always
+// print one entry per line, otherwise --format, or grid on a terminal and one per line when piped
+fn output_format(arg: &Arg, stdout_is_tty: bool) -> OutputFormat {
+    if arg.format == Some(OutputFormat::Json) {
+        return OutputFormat::Json;
     }
+    if arg.long_format || arg.format_string.is_some() || arg.overview.is_some() || arg.tree {
+        return OutputFormat::SingleColumn;
+    }
+    match arg.format {
+        Some(format) => format,
+        None if stdout_is_tty => OutputFormat::Grid,
+        None => OutputFormat::SingleColumn,
+    }
+}
 
-    #[cfg(not(any(target_os = "unix", target_os = "windows")))]
-    {
-        String::from("UNKNOWN")
+// Function to decide whether to print colors for --color WHEN; in auto mode NO_COLOR
+// turns colors off whatever its value (even empty), otherwise only a terminal gets them
+fn color_enabled(when: ColorWhen, no_color: Option<&std::ffi::OsStr>, stdout_is_tty: bool) -> bool {
+    match when {
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => no_color.is_none() && stdout_is_tty,
     }
 }
 
-// Struct to hold file entry information
-#[derive(Debug)]
-struct Entry {
-    name: String,
-    modified: SystemTime,
-    size: u64,
-    attribute: u32,
+// Function to find the terminal width: asked of the terminal on stdout where there is one,
+// else taken from $COLUMNS, else the usual 80
+fn terminal_width() -> usize {
+    tty_width()
+        .or_else(|| {
+            std::env::var("COLUMNS")
+                .ok()
+                .and_then(|columns| columns.trim().parse().ok())
+        })
+        .filter(|&width| width > 0)
+        .unwrap_or(80)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Function to read the width of the terminal on stdout with the TIOCGWINSZ ioctl
+// None when stdout isn't a terminal (or off Linux and macOS)
+fn tty_width() -> Option<usize> {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        #[cfg(target_os = "linux")]
+        const TIOCGWINSZ: std::ffi::c_ulong = 0x5413;
+        #[cfg(target_os = "macos")]
+        const TIOCGWINSZ: std::ffi::c_ulong = 0x4008_7468;
 
-    #[test]
-    fn test_format_size() {
-        assert_eq!(format_size(500), "500B");
-        assert_eq!(format_size(2048), "2.0K");
-        assert_eq!(format_size(5 * 1024 * 1024), "5.0M");
-        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0G");
+        let mut size = ffi::Winsize::default();
+        // SAFETY: TIOCGWINSZ fills in a struct winsize, which `size` is laid out as
+        let status = unsafe { ffi::ioctl(1, TIOCGWINSZ, &mut size as *mut ffi::Winsize) };
+        (status == 0 && size.ws_col > 0).then_some(usize::from(size.ws_col))
     }
-
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        None
+    }
+}
+
+// Function to lay out the entries in a grid and keep the whole rows that fit in
+// --max-output-bytes, so the cap applies to what is actually printed
+fn grid_output(entries: &[String], width: usize, arg: &Arg, budget: &mut Option<usize>) -> String {
+    let rows = grid_layout(entries, width)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    limit_output_bytes(rows, "\n", "rows", arg, budget).join("\n")
+}
+
+// Function to count the characters that take up space on screen, skipping color escape codes
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.clone().next() == Some(']') {
+            // Skip an OSC sequence like a hyperlink, ended by ESC \ or BEL
+            while let Some(c) = chars.next() {
+                if c == '\x07' || (c == '\x1b' && chars.next() == Some('\\')) {
+                    break;
+                }
+            }
+        } else if c == '\x1b' {
+            // Skip an escape sequence like "\x1b[34m" up to its final 'm'
+            for c in chars.by_ref() {
+                if c == 'm' {
+                    break;
+                }
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+// Function to lay names out in columns like `ls -C`: filled top to bottom, then left to right,
+// using as many columns as fit in `width`
+fn grid_layout(names: &[String], width: usize) -> String {
+    const GAP: usize = 2;
+    let lengths: Vec<usize> = names.iter().map(|name| visible_width(name)).collect();
+
+    for cols in (1..=names.len()).rev() {
+        let rows = names.len().div_ceil(cols);
+        // Skip column counts that would leave the last column empty
+        if cols > 1 && (cols - 1) * rows >= names.len() {
+            continue;
+        }
+        let col_widths: Vec<usize> = lengths
+            .chunks(rows)
+            .map(|column| column.iter().copied().max().unwrap_or(0))
+            .collect();
+        let total = col_widths.iter().sum::<usize>() + GAP * (cols - 1);
+        if total > width && cols > 1 {
+            continue;
+        }
+
+        let mut lines = Vec::with_capacity(rows);
+        for row in 0..rows {
+            let mut line = String::new();
+            for (col, col_width) in col_widths.iter().enumerate() {
+                let Some(name) = names.get(col * rows + row) else {
+                    break;
+                };
+                line.push_str(name);
+                let padding = col_width + GAP - lengths[col * rows + row];
+                line.push_str(&" ".repeat(padding));
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        return lines.join("\n");
+    }
+    String::new()
+}
+
+// Function to drop entries whose real path is already in `seen`, recording the rest
+// The root is canonicalized once and entry paths are joined onto it, which is enough
+// unless `--walk logical` followed symlinks inside the tree; then each entry is resolved
+fn dedup_entries(
+    entries: Vec<Entry>,
+    root: &str,
+    arg: &Arg,
+    seen: &mut HashSet<PathBuf>,
+) -> Vec<Entry> {
+    let canonical_root = std::fs::canonicalize(root).unwrap_or_else(|_| PathBuf::from(root));
+    entries
+        .into_iter()
+        .filter(|entry| {
+            let relative = entry.path.strip_prefix(root).unwrap_or(&entry.path);
+            let joined = canonical_root.join(relative);
+            let real_path = if arg.walk == WalkMode::Logical {
+                std::fs::canonicalize(&entry.path).unwrap_or(joined)
+            } else {
+                joined
+            };
+            seen.insert(real_path)
+        })
+        .collect()
+}
+
+// Function to warn about path arguments that sit inside (or repeat) another one,
+// since a recursive listing would show their entries twice
+fn overlap_warnings(paths: &[String]) -> Vec<String> {
+    let canonical: Vec<Option<PathBuf>> = paths
+        .iter()
+        .map(|path| std::fs::canonicalize(path).ok())
+        .collect();
+    let mut warnings = Vec::new();
+    for (i, inner) in canonical.iter().enumerate() {
+        for (j, outer) in canonical.iter().enumerate() {
+            let (Some(inner), Some(outer)) = (inner, outer) else {
+                continue;
+            };
+            let overlaps = if inner == outer {
+                i > j
+            } else {
+                inner.starts_with(outer)
+            };
+            if i != j && overlaps {
+                warnings.push(format!(
+                    "{} overlaps {}, its entries will be listed twice (use --dedup)",
+                    paths[i], paths[j]
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+// Function to build the --overview listing: each entry, and under every directory
+// up to `limit` of its immediate children, indented, with a note about the rest
+fn overview_lines(
+    entries: Vec<Entry>,
+    limit: usize,
+    arg: &Arg,
+    warnings: &mut Warnings,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    for entry in entries {
+        let is_dir = entry.kind == FileKind::Directory;
+        let path = entry.path.clone();
+        lines.extend(format_entries(vec![entry], arg));
+        if !is_dir {
+            continue;
+        }
+
+        let children = match collect_entries(&path.to_string_lossy(), arg, warnings) {
+            Ok(children) => sort_entries(should_display(children, arg), arg, warnings),
+            Err(e) => {
+                warnings.count(Some(io_error_kind(&e)));
+                warnings.push(format!("{:#}", e));
+                continue;
+            }
+        };
+        let total = children.len();
+        let shown: Vec<Entry> = children.into_iter().take(limit).collect();
+        for line in format_entries(shown, arg) {
+            lines.push(format!("    {}", line));
+        }
+        if total > limit {
+            lines.push(format!("    ... {} more", total - limit));
+        }
+    }
+    lines
+}
+
+// Function to lay out sorted entries as a tree for --tree: each entry is formatted as usual,
+// then placed under its parent behind ├── / └── connectors, with │ carrying on the lines of
+// ancestors that still have siblings to come. Siblings keep the sort order.
+fn tree_lines(root: &str, entries: Vec<Entry>, arg: &Arg) -> Vec<String> {
+    let paths: Vec<PathBuf> = entries.iter().map(|entry| entry.path.clone()).collect();
+    let mut children: HashMap<&Path, Vec<usize>> = HashMap::new();
+    for (index, path) in paths.iter().enumerate() {
+        if let Some(parent) = path.parent() {
+            children.entry(parent).or_default().push(index);
+        }
+    }
+    let formatted = format_entries(entries, arg);
+
+    let mut lines = Vec::new();
+    tree_branch(
+        Path::new(root),
+        "",
+        &children,
+        &paths,
+        &formatted,
+        &mut lines,
+    );
+    lines
+}
+
+// Function to append the lines for one directory's children in tree_lines, recursing into
+// subdirectories; `prefix` holds the │ and spaces inherited from the ancestors
+fn tree_branch(
+    dir: &Path,
+    prefix: &str,
+    children: &HashMap<&Path, Vec<usize>>,
+    paths: &[PathBuf],
+    formatted: &[String],
+    lines: &mut Vec<String>,
+) {
+    let Some(indices) = children.get(dir) else {
+        return;
+    };
+    for (position, &index) in indices.iter().enumerate() {
+        let is_last = position + 1 == indices.len();
+        let connector = if is_last { "└── " } else { "├── " };
+        lines.push(format!("{}{}{}", prefix, connector, formatted[index]));
+        let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+        tree_branch(
+            &paths[index],
+            &child_prefix,
+            children,
+            paths,
+            formatted,
+            lines,
+        );
+    }
+}
+
+// Function to render entries as a Graphviz DOT graph for --dot
+// Nodes are keyed by path, directories are boxes and files ellipses, edges go parent -> child
+fn dot_graph(root: &str, entries: &[Entry]) -> String {
+    let mut lines = vec![
+        format!("digraph {} {{", dot_quote(root)),
+        format!("    {} [shape=box];", dot_quote(root)),
+    ];
+    for entry in entries {
+        let path = entry.path.display().to_string();
+        let shape = if entry.kind == FileKind::Directory {
+            "box"
+        } else {
+            "ellipse"
+        };
+        lines.push(format!(
+            "    {} [label={}, shape={}];",
+            dot_quote(&path),
+            dot_quote(&entry.name),
+            shape
+        ));
+        if let Some(parent) = entry.path.parent() {
+            lines.push(format!(
+                "    {} -> {};",
+                dot_quote(&parent.display().to_string()),
+                dot_quote(&path)
+            ));
+        }
+    }
+    lines.push("}".to_string());
+    lines.join("\n")
+}
+
+// Function to quote a string as a DOT identifier
+fn dot_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Function to render walked trees as JSON shaped like `tree -J`: an array with one
+// directory object per root (children nested under "contents") and a trailing report
+// Each directory also has an "entry_count" of the children listed under it
+fn tree_json(trees: &[(&str, Vec<Entry>)]) -> String {
+    let mut counts = (0, 0); // (directories, files), roots not included, as in tree
+    let mut roots = Vec::new();
+    for (root, entries) in trees {
+        let mut children: HashMap<&Path, Vec<&Entry>> = HashMap::new();
+        for entry in entries {
+            if let Some(parent) = entry.path.parent() {
+                children.entry(parent).or_default().push(entry);
+            }
+        }
+        let mut lines = vec![format!(
+            "  {{\"type\":\"directory\",\"name\":{},\"entry_count\":{},\"contents\":[",
+            json_string(root),
+            children.get(Path::new(root)).map_or(0, Vec::len)
+        )];
+        tree_json_contents(Path::new(root), &children, 2, &mut counts, &mut lines);
+        lines.push("  ]}".to_string());
+        roots.push(lines.join("\n"));
+    }
+    let report = format!(
+        "  {{\"type\":\"report\",\"directories\":{},\"files\":{}}}",
+        counts.0, counts.1
+    );
+    // When every root failed there is only the report, with no comma before it
+    if roots.is_empty() {
+        format!("[\n{}\n]", report)
+    } else {
+        format!("[\n{}\n,\n{}\n]", roots.join(",\n"), report)
+    }
+}
+
+// Function to append the "contents" lines of one directory for tree_json, recursing into subdirectories
+fn tree_json_contents(
+    dir: &Path,
+    children: &HashMap<&Path, Vec<&Entry>>,
+    depth: usize,
+    counts: &mut (usize, usize),
+    lines: &mut Vec<String>,
+) {
+    let Some(entries) = children.get(dir) else {
+        return;
+    };
+    let indent = "  ".repeat(depth);
+    for (i, entry) in entries.iter().enumerate() {
+        let name = json_string(&entry.name);
+        let comma = if i + 1 < entries.len() { "," } else { "" };
+        match entry.kind {
+            FileKind::Directory => {
+                counts.0 += 1;
+                lines.push(format!(
+                    "{}{{\"type\":\"directory\",\"name\":{},\"entry_count\":{},\"contents\":[",
+                    indent,
+                    name,
+                    children.get(entry.path.as_path()).map_or(0, Vec::len)
+                ));
+                tree_json_contents(&entry.path, children, depth + 1, counts, lines);
+                lines.push(format!("{}]}}{}", indent, comma));
+            }
+            FileKind::Symlink => {
+                counts.1 += 1;
+                let target = entry
+                    .link_target
+                    .as_ref()
+                    .map(|target| target.display().to_string())
+                    .unwrap_or_default();
+                lines.push(format!(
+                    "{}{{\"type\":\"link\",\"name\":{},\"target\":{}}}{}",
+                    indent,
+                    name,
+                    json_string(&target),
+                    comma
+                ));
+            }
+            _ => {
+                counts.1 += 1;
+                lines.push(format!(
+                    "{}{{\"type\":\"file\",\"name\":{}}}{}",
+                    indent, name, comma
+                ));
+            }
+        }
+    }
+}
+
+// Function to build the --manifest text: one "size<TAB>mtime<TAB>checksum<TAB>path" line per
+// file, sorted by path so two manifests can be diffed. The mtime is in Unix seconds, and the
+// checksum is "-" without --checksum or for anything but a regular file. In paths, a
+// backslash, tab or newline is escaped as \\, \t or \n.
+fn manifest_lines(mut files: Vec<Entry>, checksum: bool, warnings: &mut Warnings) -> String {
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut manifest = String::new();
+    for file in files {
+        let mtime = file
+            .modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |age| age.as_secs());
+        let crc = if checksum && file.kind == FileKind::File {
+            match crc32(&file.path) {
+                Ok(crc) => format!("{:08x}", crc),
+                Err(e) => {
+                    warnings.push(format!("{}: {}", file.path.display(), e));
+                    warnings.count(Some(e.kind()));
+                    "-".to_string()
+                }
+            }
+        } else {
+            "-".to_string()
+        };
+        let path = file
+            .path
+            .display()
+            .to_string()
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n");
+        manifest.push_str(&format!("{}\t{}\t{}\t{}\n", file.size, mtime, crc, path));
+    }
+    manifest
+}
+
+// Function to compute the CRC-32 (the IEEE one used by gzip and zip) of a file's contents
+fn crc32(path: &Path) -> std::io::Result<u32> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buffer = [0u8; 8192];
+    let mut crc = !0u32;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for &byte in &buffer[..read] {
+            crc ^= u32::from(byte);
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+    }
+    Ok(!crc)
+}
+
+// Version of the --format json layout, in its envelope; bump it when the schema changes
+const JSON_SCHEMA_VERSION: u32 = 1;
+
+// Function to wrap a --format json listing as {"version": N, "entries": listing}
+fn json_envelope(listing: &str) -> String {
+    format!(
+        "{{\n  \"version\": {},\n  \"entries\": {}\n}}",
+        JSON_SCHEMA_VERSION,
+        listing.replace('\n', "\n  ")
+    )
+}
+
+// Function to render listings for --format json: a plain array of entries, or with
+// `keyed` (more than one path given) an object mapping each path to its array
+fn json_listing(listings: &[(&str, Vec<Entry>)], keyed: bool) -> String {
+    let array = |entries: &[Entry], indent: &str| {
+        if entries.is_empty() {
+            return "[]".to_string();
+        }
+        let objects: Vec<String> = entries
+            .iter()
+            .map(|entry| format!("{}  {}", indent, json_entry(entry)))
+            .collect();
+        format!("[\n{}\n{}]", objects.join(",\n"), indent)
+    };
+    if !keyed {
+        // A single path that couldn't be listed still prints an (empty) array
+        return match listings.first() {
+            Some((_, entries)) => array(entries, ""),
+            None => "[]".to_string(),
+        };
+    }
+    let members: Vec<String> = listings
+        .iter()
+        .map(|(path, entries)| format!("  {}: {}", json_string(path), array(entries, "  ")))
+        .collect();
+    format!("{{\n{}\n}}", members.join(",\n"))
+}
+
+// Function to render one entry as a JSON object; the permission bits are the Unix mode
+// (e.g. 420 for 0o644) or the raw Windows attribute flags
+fn json_entry(entry: &Entry) -> String {
+    let modified: DateTime<Local> = entry.modified.into();
+    let permissions = if cfg!(unix) {
+        entry.attribute & 0o7777
+    } else {
+        entry.attribute
+    };
+    let mut fields = vec![
+        format!("\"name\":{}", json_string(&entry.name)),
+        format!(
+            "\"path\":{}",
+            json_string(&entry.path.display().to_string())
+        ),
+        format!("\"size\":{}", entry.size),
+        format!("\"modified\":{}", json_string(&modified.to_rfc3339())),
+        format!("\"is_dir\":{}", entry.kind == FileKind::Directory),
+        format!("\"permissions\":{}", permissions),
+    ];
+    if let Some((user, group)) = owner_names(entry) {
+        fields.push(format!("\"owner\":{}", json_string(&user)));
+        fields.push(format!("\"group\":{}", json_string(&group)));
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+// Function to quote a string as a JSON string literal
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Struct to hold warnings from the walk, with a count of each kind of I/O error
+#[derive(Debug, Default)]
+struct Warnings {
+    messages: Vec<String>,
+    permission_denied: usize,
+    not_found: usize,
+    other_io: usize,
+    loops: usize,        // symlink loops found (and skipped) while following links
+    failed_paths: usize, // paths given that couldn't be read at all
+}
+
+impl Warnings {
+    fn push(&mut self, message: String) {
+        self.messages.push(message);
+    }
+
+    // Report a path that couldn't be listed right away (never held back) and count it
+    fn fail_path(&mut self, error: anyhow::Error) {
+        eprintln!("Error: {:#}", error);
+        self.failed_paths += 1;
+    }
+
+    // Tally an I/O error by kind (walker errors without an I/O cause, like loops, aren't counted)
+    fn count(&mut self, kind: Option<std::io::ErrorKind>) {
+        match kind {
+            Some(std::io::ErrorKind::PermissionDenied) => self.permission_denied += 1,
+            Some(std::io::ErrorKind::NotFound) => self.not_found += 1,
+            Some(_) => self.other_io += 1,
+            None => {}
+        }
+    }
+}
+
+// Function to find the I/O error kind behind an error from the walk or a metadata read
+fn io_error_kind(error: &anyhow::Error) -> std::io::ErrorKind {
+    error
+        .chain()
+        .find_map(|cause| {
+            cause
+                .downcast_ref::<std::io::Error>()
+                .map(|io| io.kind())
+                .or_else(|| {
+                    cause
+                        .downcast_ref::<walkdir::Error>()
+                        .and_then(|walk| walk.io_error())
+                        .map(|io| io.kind())
+                })
+        })
+        .unwrap_or(std::io::ErrorKind::Other)
+}
+
+// Function to print pending warnings to stderr, unless --errors-at-end holds them back
+fn flush_warnings(warnings: &mut Warnings, arg: &Arg) {
+    if arg.errors_at_end {
+        return;
+    }
+    for warning in warnings.messages.drain(..) {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+// Function to build the collated warning block printed by --errors-at-end
+fn warning_summary(warnings: &[String]) -> String {
+    let mut summary = format!("{} warning(s):\n", warnings.len());
+    for warning in warnings {
+        summary.push_str(&format!("  Warning: {}\n", warning));
+    }
+    summary
+}
+
+// Function to build the directory walker for a path, recursive or not based on arg.recursive
+// Symlinks are followed according to --walk (or --follow-only-dirs)
+fn build_walker(path: &str, arg: &Arg) -> WalkDir {
+    let walker = if arg.recursive {
+        match arg.max_depth {
+            Some(depth) => WalkDir::new(path).min_depth(1).max_depth(depth),
+            None => WalkDir::new(path).min_depth(1),
+        }
+    } else {
+        WalkDir::new(path).max_depth(1).min_depth(1)
+    };
+    if arg.follow_only_dirs {
+        return walker.follow_links(true);
+    }
+    match arg.walk {
+        WalkMode::Physical => walker.follow_links(false).follow_root_links(false),
+        WalkMode::Logical => walker.follow_links(true),
+        WalkMode::CommandLineLogical => walker.follow_links(false).follow_root_links(true),
+    }
+}
+
+// Function to iterate over the walk, not descending into hidden directories for
+// --skip-hidden-dirs (the directory itself is still yielded, so -a can show it), and
+// leaving out whatever Git ignores for --gitignore (ignored directories aren't entered)
+// and directories holding a --respect-markers file (neither listed nor entered)
+// Under --follow-only-dirs the walker follows every link, and links whose target isn't a
+// directory are handed on as links (see entry_from) so their targets aren't counted twice
+fn walk_entries(
+    path: &str,
+    arg: &Arg,
+) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> + use<> {
+    let mut walker = build_walker(path, arg).into_iter();
+    let skip_hidden_dirs = arg.skip_hidden_dirs;
+    let marker = arg.respect_markers.clone();
+    let mut gitignore = arg.gitignore.then(|| GitIgnore::new(Path::new(path)));
+    std::iter::from_fn(move || {
+        loop {
+            let next = walker.next()?;
+            if let Ok(dir_entry) = &next {
+                let is_dir = dir_entry.file_type().is_dir();
+                if let Some(gitignore) = &mut gitignore
+                    && gitignore.is_ignored(dir_entry.path(), is_dir)
+                {
+                    if is_dir {
+                        walker.skip_current_dir();
+                    }
+                    continue;
+                }
+                if let Some(marker) = &marker
+                    && is_dir
+                    && dir_entry.path().join(marker).exists()
+                {
+                    walker.skip_current_dir();
+                    continue;
+                }
+                if skip_hidden_dirs
+                    && is_dir
+                    && dir_entry.file_name().to_string_lossy().starts_with('.')
+                {
+                    walker.skip_current_dir();
+                }
+            }
+            return Some(next);
+        }
+    })
+}
+
+// Struct to hold the .gitignore-style rules for --gitignore, loaded once per directory
+// Paths are matched relative to the top of the Git work tree (or the listed directory
+// outside a repository), so rules from files above the listed directory still apply
+struct GitIgnore {
+    root: PathBuf,                              // the listed directory, as walked
+    top: PathBuf,                               // the work tree top, where .git is
+    root_in_top: PathBuf,                       // the listed directory relative to `top`
+    base: Vec<IgnoreRule>, // global excludes and .git/info/exclude (lowest precedence)
+    per_dir: HashMap<PathBuf, Vec<IgnoreRule>>, // .gitignore then .ignore, keyed by directory
+}
+
+impl GitIgnore {
+    fn new(root: &Path) -> GitIgnore {
+        let absolute = std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let top = absolute
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .unwrap_or(&absolute)
+            .to_path_buf();
+        let mut base = Vec::new();
+        if let Some(global) = global_excludes_file() {
+            base.extend(read_ignore_file(&global));
+        }
+        base.extend(read_ignore_file(&top.join(".git/info/exclude")));
+        GitIgnore {
+            root: root.to_path_buf(),
+            root_in_top: absolute
+                .strip_prefix(&top)
+                .unwrap_or(Path::new(""))
+                .to_path_buf(),
+            top,
+            base,
+            per_dir: HashMap::new(),
+        }
+    }
+
+    // Check a walked path: every rule from the top down is tried and the last match wins,
+    // so deeper files and later lines override earlier ones, and "!" re-includes
+    fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let Ok(below_root) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let segments: Vec<String> = self
+            .root_in_top
+            .join(below_root)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        if segments.last().is_some_and(|name| name == ".git") {
+            return true; // Git's own directory is never part of the work tree
+        }
+        let mut ignored = rule_verdict(&self.base, &segments, is_dir).unwrap_or(false);
+        for depth in 0..segments.len() {
+            let dir = segments[..depth]
+                .iter()
+                .fold(self.top.clone(), |dir, s| dir.join(s));
+            let rules = self.per_dir.entry(dir.clone()).or_insert_with(|| {
+                let mut rules = read_ignore_file(&dir.join(".gitignore"));
+                rules.extend(read_ignore_file(&dir.join(".ignore")));
+                rules
+            });
+            if let Some(verdict) = rule_verdict(rules, &segments[depth..], is_dir) {
+                ignored = verdict;
+            }
+        }
+        ignored
+    }
+}
+
+// Struct to hold one line of a .gitignore-style file
+#[derive(Debug)]
+struct IgnoreRule {
+    segments: Vec<IgnoreSegment>, // the pattern split on "/"
+    anchored: bool, // had a "/" before the end, so it matches from the file's directory
+    negated: bool,  // started with "!"
+    dir_only: bool, // ended with "/"
+}
+
+#[derive(Debug)]
+enum IgnoreSegment {
+    AnyDirs, // "**"
+    Name(Glob),
+}
+
+// Function to find the user's global Git excludes file ($XDG_CONFIG_HOME/git/ignore or
+// ~/.config/git/ignore); a core.excludesFile setting in .gitconfig is not read
+fn global_excludes_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("git/ignore"))
+}
+
+// Function to find our own global ignore file ($XDG_CONFIG_HOME/lister/ignore or
+// ~/.config/lister/ignore), whose patterns are added to --ignore
+fn global_ignore_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("lister/ignore"))
+}
+
+// Function to find the user's configuration directory: $XDG_CONFIG_HOME, else ~/.config
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+// Function to read a file of --ignore globs, one per line; blank lines, # comments and
+// invalid patterns are skipped, and a missing file has none
+fn read_glob_file(path: &Path) -> Vec<Glob> {
+    std::fs::read_to_string(path)
+        .map(|contents| {
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .filter_map(|line| parse_glob(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Function to read the rules of a .gitignore-style file (a missing file has none)
+fn read_ignore_file(path: &Path) -> Vec<IgnoreRule> {
+    std::fs::read_to_string(path)
+        .map(|contents| contents.lines().filter_map(parse_ignore_rule).collect())
+        .unwrap_or_default()
+}
+
+// Function to parse one .gitignore line; blank lines and # comments give None
+fn parse_ignore_rule(line: &str) -> Option<IgnoreRule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (line, negated) = match line.strip_prefix('!') {
+        Some(rest) => (rest, true),
+        None => (line.strip_prefix('\\').unwrap_or(line), false), // "\#" and "\!" are literal
+    };
+    let (line, dir_only) = match line.strip_suffix('/') {
+        Some(rest) => (rest, true),
+        None => (line, false),
+    };
+    let anchored = line.contains('/');
+    let segments = line
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| match segment {
+            "**" => Some(IgnoreSegment::AnyDirs),
+            _ => parse_glob(segment).ok().map(IgnoreSegment::Name),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if segments.is_empty() {
+        return None;
+    }
+    Some(IgnoreRule {
+        segments,
+        anchored,
+        negated,
+        dir_only,
+    })
+}
+
+// Function to apply rules to a path given as segments below their file's directory:
+// Some(true) if the last matching rule ignores it, Some(false) if it re-includes it
+fn rule_verdict(rules: &[IgnoreRule], segments: &[String], is_dir: bool) -> Option<bool> {
+    let mut verdict = None;
+    for rule in rules {
+        if rule.dir_only && !is_dir {
+            continue;
+        }
+        let matched = if rule.anchored {
+            ignore_segments_match(&rule.segments, segments)
+        } else {
+            // A pattern without a "/" matches the name at any depth
+            segments.last().is_some_and(|name| {
+                ignore_segments_match(&rule.segments, std::slice::from_ref(name))
+            })
+        };
+        if matched {
+            verdict = Some(!rule.negated);
+        }
+    }
+    verdict
+}
+
+// Function to match pattern segments against path segments, "**" standing for any number of directories
+fn ignore_segments_match(pattern: &[IgnoreSegment], segments: &[String]) -> bool {
+    match pattern.split_first() {
+        None => segments.is_empty(),
+        Some((IgnoreSegment::AnyDirs, rest)) => {
+            (0..=segments.len()).any(|skip| ignore_segments_match(rest, &segments[skip..]))
+        }
+        Some((IgnoreSegment::Name(glob), rest)) => {
+            segments.split_first().is_some_and(|(first, tail)| {
+                glob.matches(first, false) && ignore_segments_match(rest, tail)
+            })
+        }
+    }
+}
+
+// Function to collect entries from a directory based on the provided path and arguments(like recursive)
+fn collect_entries(path: &str, arg: &Arg, warnings: &mut Warnings) -> Result<Vec<Entry>> {
+    let mut results = Vec::new();
+    // Number of immediate children seen per directory, for --warn-large-dir
+    let mut child_counts: HashMap<PathBuf, usize> = HashMap::new();
+
+    for entry in walk_entries(path, arg) {
+        if interrupted() {
+            break;
+        }
+        match entry {
+            Ok(dir_entry) => {
+                // Warn as soon as a directory goes over the limit, without waiting for the walk
+                if let Some(limit) = arg.warn_large_dir
+                    && let Some(parent) = dir_entry.path().parent()
+                {
+                    let count = child_counts.entry(parent.to_path_buf()).or_default();
+                    *count += 1;
+                    if *count == limit + 1 {
+                        warnings.push(format!(
+                            "{} has more than {} entries",
+                            parent.display(),
+                            limit
+                        ));
+                    }
+                }
+                // An entry whose metadata can't be read is skipped with a warning
+                let entry_data = match entry_from(&dir_entry, arg) {
+                    Ok(entry_data) => entry_data,
+                    Err(e) => {
+                        warnings.count(Some(io_error_kind(&e)));
+                        warnings.push(format!("{:#}", e));
+                        continue;
+                    }
+                };
+                // Drop oversized files right away so big trees don't keep them around
+                if is_too_large(&entry_data, arg) {
+                    continue;
+                }
+                results.push(entry_data);
+            }
+            Err(e) => {
+                if let Some(entry_data) = broken_link_entry(&e) {
+                    results.push(entry_data);
+                    continue;
+                }
+                // The path itself can't be read, so there is nothing to list
+                if e.depth() == 0 {
+                    return Err(anyhow::Error::msg(e.to_string()));
+                }
+                warnings.count(e.io_error().map(|io| io.kind()));
+                if e.loop_ancestor().is_some() {
+                    warnings.loops += 1;
+                }
+                warnings.push(e.to_string());
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+// Function to check whether a path has at least one entry that would be displayed (for --exists)
+// Stops walking at the first match instead of collecting the whole tree
+fn has_visible_entry(path: &str, arg: &Arg) -> bool {
+    any_visible(walk_entries(path, arg), arg)
+}
+
+// Function to check walked entries one at a time, pulling no more once one is visible
+fn any_visible(walk: impl Iterator<Item = walkdir::Result<walkdir::DirEntry>>, arg: &Arg) -> bool {
+    walk.filter_map(|entry| entry.ok())
+        .filter_map(|dir_entry| entry_from(&dir_entry, arg).ok())
+        .any(|entry| is_visible(&entry, arg))
+}
+
+// Function to turn a walkdir entry into our Entry, reading its metadata
+// With --follow-only-dirs a followed link to anything but a directory stays a link
+fn entry_from(dir_entry: &walkdir::DirEntry, arg: &Arg) -> Result<Entry> {
+    let unfollowed_link =
+        arg.follow_only_dirs && dir_entry.path_is_symlink() && !dir_entry.file_type().is_dir();
+    let meta_data = if unfollowed_link {
+        std::fs::symlink_metadata(dir_entry.path()).map_err(anyhow::Error::from)
+    } else {
+        dir_entry.metadata().map_err(anyhow::Error::from)
+    }
+    .with_context(|| format!("Failed to read metadata for {}", dir_entry.path().display()))?;
+
+    let file_type = dir_entry.file_type();
+    let kind = if unfollowed_link || file_type.is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_dir() {
+        FileKind::Directory
+    } else {
+        special_file_kind(&file_type)
+    };
+    entry_with(dir_entry.path(), dir_entry.depth(), kind, &meta_data)
+}
+
+// Function to keep a symlink whose target is missing, which the walk reports as an error
+// when it follows links (--walk logical); None for every other walk error
+fn broken_link_entry(error: &walkdir::Error) -> Option<Entry> {
+    let path = error.path()?;
+    let meta_data = std::fs::symlink_metadata(path).ok()?;
+    if !meta_data.file_type().is_symlink() || std::fs::metadata(path).is_ok() {
+        return None;
+    }
+    entry_with(path, error.depth(), FileKind::Symlink, &meta_data).ok()
+}
+
+// Function to build an Entry from a path and its metadata (the link's own for symlinks
+// that aren't followed)
+fn entry_with(
+    path: &Path,
+    depth: usize,
+    kind: FileKind,
+    meta_data: &std::fs::Metadata,
+) -> Result<Entry> {
+    let attribute: u32;
+
+    #[cfg(unix)]
+    {
+        attribute = meta_data.permissions().mode();
+    }
+    #[cfg(target_os = "windows")]
+    {
+        attribute = meta_data.file_attributes();
+    }
+    #[cfg(not(any(unix, target_os = "windows")))]
+    {
+        attribute = 0;
+    }
+
+    // Where a symlink points; a failed read_link leaves None and shows as "(unreadable)"
+    let link_target = if kind == FileKind::Symlink {
+        std::fs::read_link(path).ok()
+    } else {
+        None
+    };
+
+    Ok(Entry {
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string()),
+        path: path.to_path_buf(),
+        modified: meta_data
+            .modified()
+            .with_context(|| format!("Failed to get modified time for {}", path.display()))?,
+        accessed: meta_data.accessed().ok(),
+        created: meta_data.created().ok(),
+        size: meta_data.len(),
+        attribute,
+        kind,
+        depth,
+        link_target,
+        broken: kind == FileKind::Symlink && std::fs::metadata(path).is_err(),
+        #[cfg(unix)]
+        uid: std::os::unix::fs::MetadataExt::uid(meta_data),
+        #[cfg(unix)]
+        gid: std::os::unix::fs::MetadataExt::gid(meta_data),
+        #[cfg(unix)]
+        inode: std::os::unix::fs::MetadataExt::ino(meta_data),
+        #[cfg(unix)]
+        nlink: std::os::unix::fs::MetadataExt::nlink(meta_data),
+        #[cfg(target_os = "macos")]
+        flags: meta_data.st_flags(),
+    })
+}
+
+// Function to check an entry against the --ignore globs. Patterns match the file name only,
+// also with -R; a pattern ending in "/" only matches directories, and an ignored directory
+// hides everything below it
+fn is_ignored(entry: &Entry, arg: &Arg) -> bool {
+    if arg.ignore.is_empty() {
+        return false;
+    }
+    let is_dir = entry.kind == FileKind::Directory;
+    if arg
+        .ignore
+        .iter()
+        .any(|glob| glob.matches(&entry.name, is_dir))
+    {
+        return true;
+    }
+    // The depth - 1 nearest ancestors are the directories walked through below the root
+    entry
+        .path
+        .ancestors()
+        .skip(1)
+        .take(entry.depth.saturating_sub(1))
+        .filter_map(|ancestor| ancestor.file_name())
+        .any(|name| {
+            let name = name.to_string_lossy();
+            arg.ignore.iter().any(|glob| glob.matches(&name, true))
+        })
+}
+
+// Function to check for device files, FIFOs and sockets, whose contents are never read:
+// opening a FIFO or a device can block forever
+fn is_special(entry: &Entry) -> bool {
+    matches!(
+        entry.kind,
+        FileKind::CharDevice | FileKind::BlockDevice | FileKind::Fifo | FileKind::Socket
+    )
+}
+
+// Function to tell device files, FIFOs and sockets apart from regular files (Unix only)
+fn special_file_kind(file_type: &std::fs::FileType) -> FileKind {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        if file_type.is_char_device() {
+            return FileKind::CharDevice;
+        } else if file_type.is_block_device() {
+            return FileKind::BlockDevice;
+        } else if file_type.is_fifo() {
+            return FileKind::Fifo;
+        } else if file_type.is_socket() {
+            return FileKind::Socket;
+        }
+    }
+    let _ = file_type;
+    FileKind::File
+}
+
+// Function to filter entries based on visibility (hidden or not)
+fn should_display(entries: Vec<Entry>, arg: &Arg) -> Vec<Entry> {
+    entries
+        .into_iter()
+        .filter(|entry| is_visible(entry, arg))
+        .collect()
+}
+
+// Function to decide if a single entry should be shown
+fn is_visible(entry: &Entry, arg: &Arg) -> bool {
+    if is_too_large(entry, arg)
+        || !in_size_range(entry, arg)
+        || !in_time_window(entry, arg, SystemTime::now())
+        || (arg.no_special && is_special(entry))
+        || is_ignored(entry, arg)
+    {
+        return false;
+    }
+    if arg.all {
+        return true;
+    }
+
+    // Filter dot files on all platforms
+    let is_dot_file = entry.name.starts_with(".");
+
+    #[cfg(target_os = "windows")]
+    let is_hidden = entry.attribute & 0x2 != 0; // Check HIDDEN attribute
+
+    #[cfg(target_os = "macos")]
+    let is_hidden = entry.flags & UF_HIDDEN != 0 || is_listed_in_dot_hidden(&entry.path); // Finder hidden
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let is_hidden = false; // No additional hidden check on Unix
+
+    !is_dot_file && !is_hidden
+}
+
+// Function to check a file against --min-size and --max-size (both inclusive)
+// Directories always pass, since their reported size says nothing about their contents
+fn in_size_range(entry: &Entry, arg: &Arg) -> bool {
+    entry.kind == FileKind::Directory
+        || (arg.min_size.is_none_or(|min| entry.size >= min)
+            && arg.max_size.is_none_or(|max| entry.size <= max))
+}
+
+// Function to check an entry's modification time against --newer-than and --older-than
+// A time in the future (clock skew) counts as modified just now
+fn in_time_window(entry: &Entry, arg: &Arg, now: SystemTime) -> bool {
+    let age = now.duration_since(entry.modified).unwrap_or_default();
+    arg.newer_than.is_none_or(|limit| age <= limit)
+        && arg.older_than.is_none_or(|limit| age > limit)
+}
+
+// Function to make sure --min-size isn't above --max-size
+fn check_size_range(arg: &Arg) -> Result<(), String> {
+    match (arg.min_size, arg.max_size) {
+        (Some(min), Some(max)) if min > max => Err(format!(
+            "--min-size ({}) is larger than --max-size ({})",
+            format_size(min),
+            format_size(max)
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Function to check a file against --exclude-larger-than (directories never count as too large)
+fn is_too_large(entry: &Entry, arg: &Arg) -> bool {
+    match arg.exclude_larger_than {
+        Some(limit) => entry.kind != FileKind::Directory && entry.size > limit,
+        None => false,
+    }
+}
+
+// Function to check if a directory's `.hidden` file lists this entry (macOS Finder convention)
+// Each directory's `.hidden` file is read once and cached
+#[cfg(target_os = "macos")]
+fn is_listed_in_dot_hidden(path: &Path) -> bool {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Mutex, OnceLock};
+
+    static HIDDEN_LISTS: OnceLock<Mutex<HashMap<PathBuf, HashSet<String>>>> = OnceLock::new();
+
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    let mut lists = HIDDEN_LISTS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+    let hidden_names = lists.entry(dir.to_path_buf()).or_insert_with(|| {
+        std::fs::read_to_string(dir.join(".hidden"))
+            .map(|contents| {
+                contents
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+    hidden_names.contains(&*name.to_string_lossy())
+}
+
+// Function to find the full paths of all directories with the given name (for --find-dirs)
+fn find_dirs(entries: &[Entry], name: &str) -> Vec<String> {
+    let mut found: Vec<String> = entries
+        .iter()
+        .filter(|entry| entry.kind == FileKind::Directory && entry.name == name)
+        .map(|entry| entry.path.display().to_string())
+        .collect();
+    found.sort();
+    found
+}
+
+// Function to sort entries based on the provided arguments
+// Problems running --sort-key-exec are reported through `warnings`
+fn sort_entries(mut entries: Vec<Entry>, arg: &Arg, warnings: &mut Warnings) -> Vec<Entry> {
+    if let Some(command) = &arg.sort_key_exec {
+        // Ask the external command for each entry's key once, then sort by key, then name
+        let mut keyed: Vec<(String, Entry)> = entries
+            .into_iter()
+            .map(|entry| (external_sort_key(command, &entry, warnings), entry))
+            .collect();
+        keyed.sort_by(|(key_a, a), (key_b, b)| {
+            key_a
+                .cmp(key_b)
+                .then_with(|| name_sort_key(&a.name, arg).cmp(&name_sort_key(&b.name, arg)))
+        });
+        entries = keyed.into_iter().map(|(_, entry)| entry).collect();
+    } else {
+        // Each key sorts in its natural direction; --reverse flips any of them the same way
+        match sort_key(arg) {
+            SortKey::Time => entries.sort_by_key(|a| std::cmp::Reverse(entry_time(a, arg.time))), // newest first
+            SortKey::Size => entries.sort_by_key(|a| std::cmp::Reverse(a.size)), // largest first
+            SortKey::Name if arg.version_sort => {
+                entries.sort_by_key(|a| natural_sort_key(&name_sort_key(&a.name, arg)))
+            }
+            SortKey::Name => entries.sort_by_key(|a| name_sort_key(&a.name, arg)), // A-Z, any case
+            SortKey::Extension => {
+                entries.sort_by_key(|a| (extension_sort_key(&a.name), name_sort_key(&a.name, arg)))
+            }
+            SortKey::Length => {
+                entries.sort_by_key(|a| (a.name.chars().count(), name_sort_key(&a.name, arg)))
+            }
+            SortKey::Owner => entries.sort_by_key(|a| {
+                (
+                    owner_names(a).map(|(user, _)| user),
+                    name_sort_key(&a.name, arg),
+                )
+            }),
+            SortKey::Group => entries.sort_by_key(|a| {
+                (
+                    owner_names(a).map(|(_, group)| group),
+                    name_sort_key(&a.name, arg),
+                )
+            }),
+            #[cfg(unix)]
+            SortKey::Links => entries.sort_by_key(|a| (a.nlink, name_sort_key(&a.name, arg))),
+            #[cfg(not(unix))]
+            SortKey::Links => entries.sort_by_key(|a| name_sort_key(&a.name, arg)),
+            SortKey::None => {}
+            SortKey::Relevance => {
+                let weights = arg.relevance_weights.unwrap_or_default();
+                let now = SystemTime::now();
+                entries.sort_by(|a, b| {
+                    relevance_score(b, &weights, now)
+                        .total_cmp(&relevance_score(a, &weights, now))
+                        .then_with(|| name_sort_key(&a.name, arg).cmp(&name_sort_key(&b.name, arg)))
+                });
+            }
+            SortKey::EmbeddedSize => entries.sort_by_key(|a| {
+                // Names without a size go last, in name order
+                let size = embedded_size(&a.name);
+                (size.is_none(), size, name_sort_key(&a.name, arg))
+            }),
+            SortKey::DateBucket => {
+                // Newest bucket first, then newest first inside a bucket
+                let now = Local::now();
+                entries.sort_by_key(|a| {
+                    let time = entry_time(a, arg.time);
+                    (date_bucket(time, now), std::cmp::Reverse(time))
+                });
+            }
+        }
+    }
+    if arg.reverse {
+        entries.reverse();
+    }
+    if arg.group_directories_first {
+        // Stable, so each group keeps the order chosen above
+        entries.sort_by_key(|entry| entry.kind != FileKind::Directory);
+    }
+    entries
+}
+
+// Function to split a name into text and number chunks for --version-sort, so "frame10"
+// becomes ["frame", 10] and sorts after ["frame", 9]
+fn natural_sort_key(name: &str) -> Vec<NaturalChunk> {
+    let mut chunks = Vec::new();
+    let mut rest = name;
+    while let Some(first) = rest.chars().next() {
+        let is_digit = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(if is_digit {
+            // Compare by digit count, then digits, so any length works without overflow
+            let digits = chunk.trim_start_matches('0');
+            NaturalChunk::Number(digits.len(), digits.to_string())
+        } else {
+            NaturalChunk::Text(chunk.to_string())
+        });
+        rest = tail;
+    }
+    chunks
+}
+
+// Function to put a modification time into its DateBucket relative to `now`
+// Weeks start on Monday; files dated in the future count as today
+fn date_bucket(modified: SystemTime, now: DateTime<Local>) -> DateBucket {
+    use chrono::Datelike;
+
+    let date = DateTime::<Local>::from(modified).date_naive();
+    let today = now.date_naive();
+    let week_start = today - chrono::Days::new(u64::from(today.weekday().num_days_from_monday()));
+    if date >= today {
+        DateBucket::Today
+    } else if today.pred_opt() == Some(date) {
+        DateBucket::Yesterday
+    } else if date >= week_start {
+        DateBucket::ThisWeek
+    } else if (date.year(), date.month()) == (today.year(), today.month()) {
+        DateBucket::ThisMonth
+    } else if date.year() == today.year() {
+        DateBucket::ThisYear
+    } else {
+        DateBucket::Older
+    }
+}
+
+// Function to score an entry for --sort relevance as a weighted sum of three parts, each
+// between 0 and 1:
+//   recency: 1 / (1 + age in days), so 1 for just now, 0.5 a day ago, 0.125 a week ago
+//   dirs:    1 for a directory, 0 otherwise
+//   size:    ln(1 + bytes) / ln(1 + 1G), capped at 1, so bigger files count for a bit more
+// With the default weights (recency:1, dirs:2, size:0.25) every directory outranks every file.
+fn relevance_score(entry: &Entry, weights: &RelevanceWeights, now: SystemTime) -> f64 {
+    let age_days = now
+        .duration_since(entry.modified)
+        .unwrap_or_default()
+        .as_secs_f64()
+        / 86_400.0;
+    let recency = 1.0 / (1.0 + age_days);
+    let dirs = if entry.kind == FileKind::Directory {
+        1.0
+    } else {
+        0.0
+    };
+    let size = ((entry.size as f64).ln_1p() / ((1u64 << 30) as f64).ln_1p()).min(1.0);
+    weights.recency * recency + weights.dirs * dirs + weights.size * size
+}
+
+// Function to get the --time field of an entry; a time the platform doesn't record falls
+// back to the modification time
+fn entry_time(entry: &Entry, field: TimeField) -> SystemTime {
+    recorded_time(entry, field).unwrap_or(entry.modified)
+}
+
+// Function to get the --time field of an entry as recorded, None when it isn't available
+fn recorded_time(entry: &Entry, field: TimeField) -> Option<SystemTime> {
+    match field {
+        TimeField::Modified => Some(entry.modified),
+        TimeField::Accessed => entry.accessed,
+        TimeField::Created => entry.created,
+    }
+}
+
+// Function to work out the sort key: --sort if given, else the -t / -S shorthands, else name
+fn sort_key(arg: &Arg) -> SortKey {
+    match arg.sort {
+        Some(key) => key,
+        None if arg.sort_by_time => SortKey::Time,
+        None if arg.sort_by_size => SortKey::Size,
+        None => SortKey::Name,
+    }
+}
+
+// Function to get the lowercased extension used by --sort extension ("" when there is none,
+// so those names come first; a leading dot alone, as in ".bashrc", is not an extension)
+fn extension_sort_key(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// Function to run the --sort-key-exec command for one entry and return its trimmed stdout
+// The command is split on whitespace and the entry name is passed as the last argument
+fn external_sort_key(command: &str, entry: &Entry, warnings: &mut Warnings) -> String {
+    let mut parts = command.split_whitespace();
+    let Some(program) = parts.next() else {
+        return String::new();
+    };
+    match std::process::Command::new(program)
+        .args(parts)
+        .arg(&entry.name)
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        Ok(output) => {
+            warnings.push(format!(
+                "sort key command failed for {} ({})",
+                entry.name, output.status
+            ));
+            String::new()
+        }
+        Err(e) => {
+            warnings.count(Some(e.kind()));
+            warnings.push(format!(
+                "failed to run sort key command '{}': {}",
+                program, e
+            ));
+            String::new()
+        }
+    }
+}
+
+// Function to build the key used for name sorting: lowercased, and without a
+// leading article like "The " when --ignore-articles is set
+// Keys compare by Unicode code point, never by locale, so a leading "." (U+002E) always
+// sorts before digits and letters: with -a the order is .a, .z, a, z everywhere
+fn name_sort_key(name: &str, arg: &Arg) -> String {
+    let name = name.to_lowercase();
+    if arg.ignore_articles {
+        for article in &arg.articles {
+            let prefix = format!("{} ", article.to_lowercase());
+            if let Some(rest) = name.strip_prefix(&prefix) {
+                return rest.to_string();
+            }
+        }
+    }
+    name
+}
+
+// Function to format entries for display based on long_format and human_readable options
+fn format_entries(entries: Vec<Entry>, arg: &Arg) -> Vec<String> {
+    // taking each entry from the Vector and formatting it based on the long_format flag and human-readable size option
+    entries
+        .into_iter()
+        .map(|f| {
+            let name = match &arg.relative_to {
+                Some(base) => relative_path(&f.path, base).display().to_string(),
+                None => f.name.clone(),
+            };
+            let name = if arg.shell_quote {
+                shell_quote(&name)
+            } else {
+                name
+            };
+            let name = if arg.classify {
+                format!("{}{}", name, classify_suffix(&f))
+            } else {
+                name
+            };
+            // Links are escape codes too, so they follow the color switch (off when piped)
+            let link = (arg.hyperlink && colored::control::SHOULD_COLORIZE.should_colorize())
+                .then(|| file_url(&f.path));
+            let line = if let Some(template) = &arg.format_string {
+                render_template(template, &f, &name, arg)
+            } else if arg.long_format {
+                let color = name_color(&f);
+                let datetime: DateTime<Local> = entry_time(&f, arg.time).into();
+                // A "*" marks a time that isn't recorded, with mtime shown instead
+                let time_note = match arg.time {
+                    TimeField::Modified => "",
+                    field if recorded_time(&f, field).is_none() => "*",
+                    _ => " ",
+                };
+                let size_display = if arg.size_both {
+                    format!("{} ({})", f.size, format_size(f.size))
+                } else if arg.human_readable {
+                    format_size_aligned(f.size)
+                } else {
+                    format!("{}B", f.size)
+                };
+                let attributes = format_attributes(&f, arg);
+                let age = arg
+                    .relative_mtime
+                    .then(|| relative_age(f.modified, SystemTime::now()));
+                let capabilities = (arg.capabilities && f.kind == FileKind::File)
+                    .then(|| file_capabilities(&f.path))
+                    .flatten();
+                let owner = owner_names(&f).map(|(user, group)| match arg.max_owner_width {
+                    Some(width) => (ellipsize(&user, width), ellipsize(&group, width)),
+                    None => (user, group),
+                });
+                long_format_line(
+                    &LongColumns {
+                        name: &name,
+                        name_color: color,
+                        link: link.as_deref(),
+                        name_suffix: &link_suffix(&f),
+                        target_color: link_target_color(&f),
+                        size: &size_display,
+                        size_bytes: Some(f.size),
+                        modified: &format!("{}{}", datetime.format("%b %d %H:%M"), time_note),
+                        attributes: &attributes,
+                        color_permissions: cfg!(unix),
+                        age: age.as_deref(),
+                        capabilities: capabilities.as_deref(),
+                        owner: owner
+                            .as_ref()
+                            .map(|(user, group)| (user.as_str(), group.as_str())),
+                    },
+                    arg,
+                )
+            } else {
+                let painted = paint(&name, name_color(&f));
+                match &link {
+                    Some(url) => hyperlink(&painted, url),
+                    None => painted,
+                }
+            };
+            if arg.show_depth {
+                format!("{} {}", f.depth, line)
+            } else {
+                line
+            }
+        })
+        .collect()
+}
+
+// Function to work out `path` relative to `base` without touching the filesystem,
+// climbing out of `base` with ".." where needed; relative inputs start from the current directory
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let absolute = |path: &Path| {
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().unwrap_or_default().join(path)
+        };
+        normalize_path(&path.to_string_lossy())
+    };
+    let (path, base) = (absolute(path), absolute(base));
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in base.components().skip(common) {
+        relative.push("..");
+    }
+    for component in path.components().skip(common) {
+        relative.push(component);
+    }
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
+// Function to pick the color for a name by file type, like GNU ls:
+// blue directories, cyan symlinks (red when broken), green executables, plain for everything else
+fn name_color(entry: &Entry) -> Option<Color> {
+    match entry.kind {
+        FileKind::Symlink if entry.broken => Some(Color::Red),
+        FileKind::Directory => Some(Color::Blue),
+        FileKind::Symlink => Some(Color::Cyan),
+        FileKind::File if is_executable(entry) => Some(Color::Green),
+        FileKind::File
+        | FileKind::CharDevice
+        | FileKind::BlockDevice
+        | FileKind::Fifo
+        | FileKind::Socket => None,
+    }
+}
+
+// Function to pick the color for a valid symlink's target by the target's own type,
+// like GNU ls: a link to a directory points at a blue name (None for broken links)
+fn link_target_color(entry: &Entry) -> Option<Color> {
+    if entry.kind != FileKind::Symlink || entry.broken {
+        return None;
+    }
+    let meta_data = std::fs::metadata(&entry.path).ok()?;
+    let kind = if meta_data.is_dir() {
+        FileKind::Directory
+    } else {
+        special_file_kind(&meta_data.file_type())
+    };
+    name_color(&entry_with(&entry.path, entry.depth, kind, &meta_data).ok()?)
+}
+
+// Function to describe where a symlink points for the long format, e.g. " -> ../target"
+fn link_suffix(entry: &Entry) -> String {
+    if entry.kind != FileKind::Symlink {
+        return String::new();
+    }
+    match (&entry.link_target, entry.broken) {
+        (Some(target), true) => format!(" -> {} (broken)", target.display()),
+        (Some(target), false) => format!(" -> {}", target.display()),
+        (None, true) => " -> (broken)".to_string(),
+        (None, false) => " -> (unreadable)".to_string(),
+    }
+}
+
+// Function to pick the --classify mark for an entry, like ls -F
+// ("" for regular files that aren't executable and for device files)
+fn classify_suffix(entry: &Entry) -> &'static str {
+    match entry.kind {
+        FileKind::Directory => "/",
+        FileKind::Symlink => "@",
+        FileKind::Fifo => "|",
+        FileKind::Socket => "=",
+        FileKind::File if is_executable(entry) => "*",
+        FileKind::File | FileKind::CharDevice | FileKind::BlockDevice => "",
+    }
+}
+
+// Function to wrap text in an OSC 8 hyperlink to `url`
+fn hyperlink(text: &str, url: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+// Function to build the file:// URL of an entry for --hyperlink from its absolute path,
+// percent-encoding every byte but letters, digits, "-._~" and the "/" separators
+fn file_url(path: &Path) -> String {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let absolute = absolute.to_string_lossy().replace('\\', "/");
+    let mut url = String::from("file://");
+    if !absolute.starts_with('/') {
+        url.push('/'); // a Windows drive, as in file:///C:/Users
+    }
+    for byte in absolute.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            url.push(char::from(byte));
+        } else {
+            url.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    url
+}
+
+// Function to check the Unix execute bits kept in `attribute` (never true on Windows)
+fn is_executable(entry: &Entry) -> bool {
+    cfg!(unix) && entry.attribute & 0o111 != 0
+}
+
+// Function to color text; `colored` already leaves it plain when stdout isn't a terminal
+fn paint(text: &str, color: Option<Color>) -> String {
+    match color {
+        Some(color) => text.color(color).to_string(),
+        None => text.to_string(),
+    }
+}
+
+// Function to render one entry with a --format-string template
+fn render_template(template: &Template, entry: &Entry, name: &str, arg: &Arg) -> String {
+    let mut line = String::new();
+    for part in &template.0 {
+        match part {
+            TemplatePart::Literal(text) => line.push_str(text),
+            TemplatePart::Field { field, align } => {
+                let value = match field {
+                    TemplateField::Name => name.to_string(),
+                    TemplateField::Path => entry.path.display().to_string(),
+                    TemplateField::Size if arg.human_readable => format_size(entry.size),
+                    TemplateField::Size => entry.size.to_string(),
+                    // The --time field, like the long format's column, with the same "*"
+                    // when it isn't recorded and mtime is shown instead
+                    TemplateField::Mtime => {
+                        let datetime: DateTime<Local> = entry_time(entry, arg.time).into();
+                        let note = if recorded_time(entry, arg.time).is_none() {
+                            "*"
+                        } else {
+                            ""
+                        };
+                        format!("{}{}", datetime.format("%b %d %H:%M"), note)
+                    }
+                    TemplateField::Perms => format_attributes(entry, arg),
+                    // Neither is recorded on Windows, so they show as "-" there
+                    TemplateField::Owner => owner_names(entry)
+                        .map(|(user, _)| user)
+                        .unwrap_or_else(|| "-".to_string()),
+                    #[cfg(unix)]
+                    TemplateField::Inode => entry.inode.to_string(),
+                    #[cfg(not(unix))]
+                    TemplateField::Inode => "-".to_string(),
+                };
+                let value = match *align {
+                    Some(('<', width)) => format!("{:<width$}", value),
+                    Some(('>', width)) => format!("{:>width$}", value),
+                    Some((_, width)) => format!("{:^width$}", value),
+                    None => value,
+                };
+                line.push_str(&value);
+            }
+        }
+    }
+    line
+}
+
+// Function to parse a --format-string template like "{size:>10} {name}"
+// `{{` and `}}` give literal braces, unknown fields are rejected up front
+fn parse_template(template: &str) -> Result<Template, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(format!("unclosed '{{' in template '{}'", template)),
+                    }
+                }
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(parse_placeholder(&placeholder)?);
+            }
+            '}' => return Err(format!("unmatched '}}' in template '{}'", template)),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(TemplatePart::Literal(literal));
+    }
+    Ok(Template(parts))
+}
+
+// Function to parse the inside of one placeholder, e.g. "size:>10"
+fn parse_placeholder(placeholder: &str) -> Result<TemplatePart, String> {
+    let (field, spec) = match placeholder.split_once(':') {
+        Some((field, spec)) => (field, Some(spec)),
+        None => (placeholder, None),
+    };
+    let field = match field {
+        "name" => TemplateField::Name,
+        "path" => TemplateField::Path,
+        "size" => TemplateField::Size,
+        "mtime" => TemplateField::Mtime,
+        "perms" => TemplateField::Perms,
+        "owner" => TemplateField::Owner,
+        "inode" => TemplateField::Inode,
+        other => {
+            return Err(format!(
+                "unknown field '{{{}}}' (expected name, path, size, mtime, perms, owner or inode)",
+                other
+            ));
+        }
+    };
+    let align = match spec {
+        None => None,
+        Some(spec) => {
+            let (fill_side, width) = match spec.chars().next() {
+                Some(c @ ('<' | '>' | '^')) => (c, &spec[1..]),
+                _ => ('<', spec),
+            };
+            let width = width
+                .parse()
+                .map_err(|_| format!("invalid width '{}' in '{{{}}}'", spec, placeholder))?;
+            Some((fill_side, width))
+        }
+    };
+    Ok(TemplatePart::Field { field, align })
+}
+
+// Function to quote a name for a POSIX shell (--shell-quote)
+// Names made only of safe characters are left alone, anything else is wrapped in
+// single quotes with embedded single quotes written as '\''
+fn shell_quote(name: &str) -> String {
+    let is_safe = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./+,:@%=".contains(c));
+    if is_safe {
+        name.to_string()
+    } else {
+        format!("'{}'", name.replace('\'', "'\\''"))
+    }
+}
+
+// Function to lay out one long-format line; columns are told apart by position,
+// and --header prints a title row through this same function so they line up
+fn long_format_line(columns: &LongColumns, arg: &Arg) -> String {
+    let widths = arg.columns.unwrap_or_default();
+    // Permissions follow the name like in ls -l, padded to their "PERMISSIONS" title
+    // (with room for an "@"/"+" marker) so the owner or size column lines up
+    let attributes_width = if cfg!(target_os = "windows") { 10 } else { 11 };
+    let mut attributes = fit_column(
+        columns.attributes,
+        widths.attributes,
+        attributes_width,
+        false,
+    );
+    let enabled = colored::control::SHOULD_COLORIZE.should_colorize();
+    if columns.color_permissions {
+        attributes = paint_permissions(&attributes, enabled);
+    }
+    let mut size = fit_column(columns.size, widths.size, 10, true);
+    if let Some(bytes) = columns.size_bytes {
+        size = paint_size(&size, bytes, enabled);
+    }
+    // One more character with --time accessed/created, for the "*" on missing times
+    let time_width = if arg.time == TimeField::Modified {
+        12
+    } else {
+        13
+    };
+    let mut modified = fit_column(columns.modified, widths.mtime, time_width, false);
+    if let Some(age) = columns.age {
+        // The age sits next to the timestamp, wide enough for "59 minutes ago"
+        modified.push_str(&format!("  {}", fit_column(age, None, 14, false)));
+    }
+    let owner = match columns.owner {
+        Some((user, group)) => format!(
+            "{}  {}  ",
+            fit_column(user, widths.owner, 8, false),
+            fit_column(group, widths.group, 8, false)
+        ),
+        None => String::new(),
+    };
+    let mut line = format!(
+        "{}  {}  {}{}  {}",
+        paint_name_column(
+            columns.name,
+            columns.name_color,
+            columns.name_suffix,
+            columns.target_color,
+            columns.link,
+            widths.name
+        ),
+        attributes,
+        owner,
+        size,
+        modified
+    );
+    if let Some(capabilities) = columns.capabilities {
+        line.push_str(&format!("  {}", capabilities));
+    }
+    // The time (or age) column is padded for alignment, which isn't needed at the end
+    line.truncate(line.trim_end().len());
+    line
+}
+
+// Function to color a permission string like "-rwxr-xr--" for quick audits: read yellow,
+// write red (bold red when world-writable), execute green, unset bits dimmed
+// The escape codes are written directly so the choice follows `enabled`, not colored's global state
+fn paint_permissions(permissions: &str, enabled: bool) -> String {
+    if !enabled {
+        return permissions.to_string();
+    }
+    permissions
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let code = match c {
+                _ if i == 0 || i > 9 => None, // file type character and padding
+                'r' => Some("33"),
+                'w' if i == 8 => Some("1;31"), // writable by others
+                'w' => Some("31"),
+                'x' | 's' | 't' => Some("32"),
+                '-' => Some("2"),
+                _ => None,
+            };
+            match code {
+                Some(code) => format!("\x1b[{}m{}\x1b[0m", code, c),
+                None => c.to_string(),
+            }
+        })
+        .collect()
+}
+
+// Function to shade a size by its magnitude so big files stand out: dim below 1K, plain
+// for kilobytes, bold for megabytes and bold bright white from a gigabyte up.
+// The padding around the size is left unpainted.
+fn paint_size(size: &str, bytes: u64, enabled: bool) -> String {
+    let code = match bytes {
+        _ if !enabled => return size.to_string(),
+        0..1024 => "2",
+        1024..1_048_576 => return size.to_string(),
+        1_048_576..1_073_741_824 => "1",
+        _ => "1;97",
+    };
+    let value = size.trim_start();
+    let padding = &size[..size.len() - value.len()];
+    format!("{}\x1b[{}m{}\x1b[0m", padding, code, value)
+}
+
+// Function to describe how long before `now` a time was, for --relative-mtime:
+// "just now" under a minute, then minutes, hours, days, months (30 days) and years (365 days)
+fn relative_age(modified: SystemTime, now: SystemTime) -> String {
+    let Ok(elapsed) = now.duration_since(modified) else {
+        return "in the future".to_string();
+    };
+    let seconds = elapsed.as_secs();
+    let (count, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86_400 => (seconds / 3600, "hour"),
+        86_400..2_592_000 => (seconds / 86_400, "day"),
+        2_592_000..31_536_000 => (seconds / 2_592_000, "month"),
+        _ => (seconds / 31_536_000, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{} ago", count, unit, plural)
+}
+
+// Function to read a file's capabilities for --capabilities from its "security.capability"
+// extended attribute, rendered like getcap; None when it has none (or off Linux)
+fn file_capabilities(path: &Path) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut value = [0u8; 24]; // the largest layout, vfs_cap_data revision 3
+        // SAFETY: both names are NUL-terminated and getxattr writes at most value.len() bytes
+        let len = unsafe {
+            ffi::getxattr(
+                path.as_ptr(),
+                c"security.capability".as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        parse_capabilities(&value[..usize::try_from(len).ok()?])
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+// Linux capability names by bit number, as getcap prints them
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const CAPABILITY_NAMES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+// Function to decode a vfs_cap_data value (little-endian: a magic/flags word, then permitted
+// and inheritable masks, 32 bits each for revision 1 and 64 bits split in halves after that)
+// into getcap's text, e.g. "cap_net_admin,cap_net_raw=ep"; capabilities with different
+// flags go in separate groups, like "cap_chown=ip cap_kill=i"
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_capabilities(value: &[u8]) -> Option<String> {
+    let word = |index: usize| -> Option<u64> {
+        let bytes = value.get(index * 4..index * 4 + 4)?;
+        Some(u64::from(u32::from_le_bytes(bytes.try_into().ok()?)))
+    };
+    let magic = word(0)?;
+    let effective = magic & 1 != 0;
+    let (permitted, inheritable) = match magic & 0xff00_0000 {
+        0x0100_0000 => (word(1)?, word(2)?),
+        0x0200_0000 | 0x0300_0000 => (word(1)? | word(3)? << 32, word(2)? | word(4)? << 32),
+        _ => return None,
+    };
+
+    // Group the capabilities by their flags, keeping the groups in bit order
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for bit in 0..64 {
+        let (p, i) = (permitted >> bit & 1 != 0, inheritable >> bit & 1 != 0);
+        if !p && !i {
+            continue;
+        }
+        let mut flags = String::new();
+        if effective {
+            flags.push('e');
+        }
+        if i {
+            flags.push('i');
+        }
+        if p {
+            flags.push('p');
+        }
+        let name = match CAPABILITY_NAMES.get(bit) {
+            Some(name) => format!("cap_{}", name),
+            None => format!("cap_{}", bit),
+        };
+        match groups
+            .iter_mut()
+            .find(|(group_flags, _)| *group_flags == flags)
+        {
+            Some((_, names)) => names.push(name),
+            None => groups.push((flags, vec![name])),
+        }
+    }
+    if groups.is_empty() {
+        return None;
+    }
+    Some(
+        groups
+            .iter()
+            .map(|(flags, names)| format!("{}={}", names.join(","), flags))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+// Function to shorten a name to at most `width` characters, marking the cut with "…"
+fn ellipsize(name: &str, width: usize) -> String {
+    if name.chars().count() <= width {
+        return name.to_string();
+    }
+    let mut short: String = name.chars().take(width.saturating_sub(1)).collect();
+    if width > 0 {
+        short.push('…');
+    }
+    short
+}
+
+// Function to get the owner and group names of an entry (None on Windows, which has no uid/gid)
+fn owner_names(entry: &Entry) -> Option<(String, String)> {
+    #[cfg(unix)]
+    {
+        Some((user_name(entry.uid), group_name(entry.gid)))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = entry;
+        None
+    }
+}
+
+#[cfg(unix)]
+mod ffi {
+    use std::ffi::c_char;
+
+    // Only the first field of struct passwd / struct group is read, and it is
+    // pw_name / gr_name on every Unix, so the rest of the layout can stay opaque
+    #[repr(C)]
+    pub struct Passwd {
+        pub pw_name: *const c_char,
+    }
+
+    #[repr(C)]
+    pub struct Group {
+        pub gr_name: *const c_char,
+    }
+
+    // struct winsize, filled in by the TIOCGWINSZ ioctl
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Winsize {
+        pub ws_row: u16,
+        pub ws_col: u16,
+        pub ws_xpixel: u16,
+        pub ws_ypixel: u16,
+    }
+
+    unsafe extern "C" {
+        pub fn getpwuid(uid: u32) -> *const Passwd;
+        pub fn getgrgid(gid: u32) -> *const Group;
+        pub fn signal(signal: i32, handler: extern "C" fn(i32)) -> usize;
+        pub fn _exit(status: i32) -> !;
+        #[cfg(target_os = "linux")]
+        pub fn getxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *mut std::ffi::c_void,
+            size: usize,
+        ) -> isize;
+        pub fn ioctl(fd: i32, request: std::ffi::c_ulong, ...) -> i32;
+        #[cfg(target_os = "linux")]
+        pub fn llistxattr(path: *const c_char, list: *mut c_char, size: usize) -> isize;
+    }
+}
+
+// Function to resolve a uid to a user name via getpwuid, falling back to the number
+// Lookups are cached, since a big directory is usually owned by one user
+#[cfg(unix)]
+fn user_name(uid: u32) -> String {
+    use std::sync::{Mutex, OnceLock};
+
+    static USERS: OnceLock<Mutex<HashMap<u32, String>>> = OnceLock::new();
+
+    let mut users = USERS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+    users
+        .entry(uid)
+        .or_insert_with(|| {
+            // SAFETY: getpwuid returns null or a pointer to a static record that stays
+            // valid until the next call; the name is copied out before the lock is released
+            unsafe {
+                let record = ffi::getpwuid(uid);
+                c_string(if record.is_null() {
+                    std::ptr::null()
+                } else {
+                    (*record).pw_name
+                })
+            }
+            .unwrap_or_else(|| uid.to_string())
+        })
+        .clone()
+}
+
+// Function to resolve a gid to a group name via getgrgid, falling back to the number
+#[cfg(unix)]
+fn group_name(gid: u32) -> String {
+    use std::sync::{Mutex, OnceLock};
+
+    static GROUPS: OnceLock<Mutex<HashMap<u32, String>>> = OnceLock::new();
+
+    let mut groups = GROUPS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap();
+    groups
+        .entry(gid)
+        .or_insert_with(|| {
+            // SAFETY: same contract as getpwuid above
+            unsafe {
+                let record = ffi::getgrgid(gid);
+                c_string(if record.is_null() {
+                    std::ptr::null()
+                } else {
+                    (*record).gr_name
+                })
+            }
+            .unwrap_or_else(|| gid.to_string())
+        })
+        .clone()
+}
+
+// Function to copy a C string returned by libc, treating null as missing
+#[cfg(unix)]
+unsafe fn c_string(ptr: *const std::ffi::c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    // SAFETY: the caller passes a valid NUL-terminated string
+    let name = unsafe { std::ffi::CStr::from_ptr(ptr) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+// Function to build the long-format name column: the name (colored) followed by a
+// suffix such as a symlink's " -> target" (its target colored by type), padded or
+// truncated like any other column
+fn paint_name_column(
+    name: &str,
+    color: Option<Color>,
+    suffix: &str,
+    target_color: Option<Color>,
+    link: Option<&str>,
+    fixed: Option<usize>,
+) -> String {
+    let column = fit_column(&format!("{}{}", name, suffix), fixed, 20, false);
+    // Byte offset of a character count, so a truncated column still splits cleanly
+    let offset = |chars: usize| {
+        column
+            .char_indices()
+            .nth(chars)
+            .map_or(column.len(), |(index, _)| index)
+    };
+    let name_end = offset(name.chars().count());
+    let painted = paint(&column[..name_end], color);
+    let rest = match target_color {
+        Some(target_color) if suffix.starts_with(" -> ") => {
+            let target_start = offset(name.chars().count() + " -> ".len());
+            let target_end = offset(name.chars().count() + suffix.chars().count());
+            format!(
+                "{}{}{}",
+                &column[name_end..target_start],
+                paint(&column[target_start..target_end], Some(target_color)),
+                &column[target_end..]
+            )
+        }
+        _ => column[name_end..].to_string(),
+    };
+    match link {
+        Some(url) => format!("{}{}", hyperlink(&painted, url), rest),
+        None => format!("{}{}", painted, rest),
+    }
+}
+
+// Function to pad a long-format column to its width
+// A width pinned with --columns also truncates, otherwise the default width is only a minimum
+// Right-aligned values may come padded already (like format_size_aligned's), and that
+// padding is dropped before truncating so the text itself is what's kept
+fn fit_column(value: &str, fixed: Option<usize>, default: usize, right_align: bool) -> String {
+    let value = if right_align {
+        value.trim_start()
+    } else {
+        value
+    };
+    let (value, width) = match fixed {
+        Some(width) => (value.chars().take(width).collect::<String>(), width),
+        None => (value.to_string(), default),
+    };
+    if right_align {
+        format!("{:>width$}", value)
+    } else {
+        format!("{:<width$}", value)
+    }
+}
+
+// Function to parse a --columns spec like "name:30,size:12,mtime:20"
+fn parse_column_widths(spec: &str) -> Result<ColumnWidths, String> {
+    let mut widths = ColumnWidths::default();
+    for part in spec.split(',').filter(|p| !p.trim().is_empty()) {
+        let (column, width) = part
+            .split_once(':')
+            .ok_or_else(|| format!("expected COLUMN:WIDTH, got '{}'", part))?;
+        let width: usize = width
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid width '{}' for column '{}'", width, column))?;
+        match column.trim() {
+            "name" => widths.name = Some(width),
+            "size" => widths.size = Some(width),
+            "mtime" => widths.mtime = Some(width),
+            "attributes" => widths.attributes = Some(width),
+            "owner" => widths.owner = Some(width),
+            "group" => widths.group = Some(width),
+            other => {
+                return Err(format!(
+                    "unknown column '{}' (expected name, size, mtime, attributes, owner or group)",
+                    other
+                ));
+            }
+        }
+    }
+    Ok(widths)
+}
+
+// Function to drop entries (or grid rows) once the joined output would go over what's
+// left of --max-output-bytes in `budget`, which is reduced by the bytes kept; the
+// truncation notice naming how many more `unit` there were is appended after them, and
+// uses up the rest of the budget so nothing else is printed
+fn limit_output_bytes(
+    entries: Vec<String>,
+    separator: &str,
+    unit: &str,
+    arg: &Arg,
+    budget: &mut Option<usize>,
+) -> Vec<String> {
+    let (Some(max_bytes), Some(left)) = (arg.max_output_bytes, budget.as_mut()) else {
+        return entries;
+    };
+
+    let total = entries.len();
+    let mut kept = Vec::new();
+    let mut used = 0;
+
+    for entry in entries {
+        let needed = if kept.is_empty() {
+            entry.len()
+        } else {
+            separator.len() + entry.len()
+        };
+        if used + needed > *left {
+            break;
+        }
+        used += needed;
+        kept.push(entry);
+    }
+
+    *left -= used;
+    if kept.len() < total {
+        *left = 0;
+        let omitted = total - kept.len();
+        kept.push(format!(
+            "... output truncated at {} bytes ({} more {})",
+            max_bytes, omitted, unit
+        ));
+    }
+    kept
+}
+
+// Function to format file sizes into human-readable strings
+fn format_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+
+    if bytes >= GB {
+        format!("{:.1}G", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
+        format!("{:.1}M", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.1}K", bytes as f64 / KB as f64)
+    } else {
+        format!("{}B", bytes)
+    }
+}
+
+// Function to format a human-readable size at a fixed width for the long format:
+// the number is right-aligned in six characters ("XXXX.X") followed by a one-letter unit,
+// so numbers and units each line up down the column whatever the magnitude
+fn format_size_aligned(bytes: u64) -> String {
+    let human = format_size(bytes);
+    let (number, unit) = human.split_at(human.len() - 1);
+    format!("{:>6}{}", number, unit)
+}
+
+// Function to parse an --ignore glob: * matches any run of characters, ? any one character,
+// [abc], [a-z] and [!a-z] a character class; anything else, including an unclosed [, is literal
+fn parse_glob(pattern: &str) -> Result<Glob, String> {
+    let (pattern, dir_only) = match pattern.strip_suffix('/') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    if pattern.is_empty() {
+        return Err("the pattern is empty".to_string());
+    }
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => tokens.push(GlobToken::AnyRun),
+            '?' => tokens.push(GlobToken::AnyChar),
+            '[' => {
+                let negated = matches!(chars.get(i + 1), Some('!' | '^'));
+                let start = if negated { i + 2 } else { i + 1 };
+                // A ']' right after the opening bracket is a member, not the end
+                let close = (start + 1..chars.len()).find(|&j| chars[j] == ']');
+                if let Some(close) = close {
+                    let members = &chars[start..close];
+                    let mut ranges = Vec::new();
+                    let mut j = 0;
+                    while j < members.len() {
+                        if j + 2 < members.len() && members[j + 1] == '-' {
+                            ranges.push((members[j], members[j + 2]));
+                            j += 3;
+                        } else {
+                            ranges.push((members[j], members[j]));
+                            j += 1;
+                        }
+                    }
+                    tokens.push(GlobToken::Class { negated, ranges });
+                    i = close;
+                } else {
+                    tokens.push(GlobToken::Literal('['));
+                }
+            }
+            c => tokens.push(GlobToken::Literal(c)),
+        }
+        i += 1;
+    }
+    Ok(Glob {
+        tokens,
+        dir_only,
+        ignore_case: false,
+    })
+}
+
+// Function to match glob tokens against a whole name
+// On a mismatch only the most recent * backtracks, taking one more character: earlier
+// stars never need to, so patterns like "*a*a*a*b" stay quadratic instead of exponential
+// With `ignore_case`, the name and the pattern's characters are both lowercased as they're compared
+fn glob_tokens_match(tokens: &[GlobToken], name: &[char], ignore_case: bool) -> bool {
+    let fold = |c: char| {
+        if ignore_case {
+            c.to_lowercase().next().unwrap_or(c)
+        } else {
+            c
+        }
+    };
+    let (mut t, mut n) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None; // (token after the *, name position)
+    while n < name.len() {
+        let matched = match tokens.get(t) {
+            Some(GlobToken::AnyRun) => {
+                last_star = Some((t + 1, n));
+                t += 1;
+                continue;
+            }
+            Some(GlobToken::Literal(literal)) => fold(name[n]) == fold(*literal),
+            Some(GlobToken::AnyChar) => true,
+            Some(GlobToken::Class { negated, ranges }) => {
+                let c = fold(name[n]);
+                ranges
+                    .iter()
+                    .any(|&(low, high)| fold(low) <= c && c <= fold(high))
+                    != *negated
+            }
+            None => false,
+        };
+        if matched {
+            t += 1;
+            n += 1;
+        } else if let Some((after_star, start)) = last_star {
+            last_star = Some((after_star, start + 1));
+            t = after_star;
+            n = start + 1;
+        } else {
+            return false;
+        }
+    }
+    tokens[t..]
+        .iter()
+        .all(|token| matches!(token, GlobToken::AnyRun))
+}
+
+// Function to find the first size with a unit in a name, like "500MB" or "1.5G" in
+// "backup-1.5G.tar", for --sort embedded-size; the unit can't run on into a word ("5Mbps")
+fn embedded_size(name: &str) -> Option<u64> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut start = 0;
+    while start < chars.len() {
+        let begins_number = chars[start].is_ascii_digit()
+            && (start == 0 || !(chars[start - 1].is_ascii_digit() || chars[start - 1] == '.'));
+        if !begins_number {
+            start += 1;
+            continue;
+        }
+        let mut end = start;
+        while end < chars.len() && chars[end].is_ascii_digit() {
+            end += 1;
+        }
+        if end + 1 < chars.len() && chars[end] == '.' && chars[end + 1].is_ascii_digit() {
+            end += 1;
+            while end < chars.len() && chars[end].is_ascii_digit() {
+                end += 1;
+            }
+        }
+        let mut unit_end = end;
+        if unit_end < chars.len() && "KMGTkmgt".contains(chars[unit_end]) {
+            unit_end += 1;
+        }
+        if unit_end < chars.len() && "Bb".contains(chars[unit_end]) {
+            unit_end += 1;
+        }
+        let has_unit = unit_end > end;
+        let ends_cleanly = chars.get(unit_end).is_none_or(|c| !c.is_alphabetic());
+        if has_unit && ends_cleanly {
+            let token: String = chars[start..unit_end].iter().collect();
+            if let Ok(size) = parse_size(&token) {
+                return Some(size);
+            }
+        }
+        start = end.max(start + 1);
+    }
+    None
+}
+
+// Function to parse --relevance-weights, e.g. "recency:1,dirs:2,size:0.25"; weights left out
+// keep their defaults
+fn parse_relevance_weights(spec: &str) -> Result<RelevanceWeights, String> {
+    let mut weights = RelevanceWeights::default();
+    for part in spec.split(',').filter(|p| !p.trim().is_empty()) {
+        let (name, weight) = part
+            .split_once(':')
+            .ok_or_else(|| format!("expected NAME:WEIGHT, got '{}'", part))?;
+        let weight: f64 = weight
+            .trim()
+            .parse()
+            .ok()
+            .filter(|weight: &f64| weight.is_finite())
+            .ok_or_else(|| format!("invalid weight '{}' for '{}'", weight, name))?;
+        match name.trim() {
+            "recency" => weights.recency = weight,
+            "dirs" => weights.dirs = weight,
+            "size" => weights.size = weight,
+            other => {
+                return Err(format!(
+                    "unknown weight '{}' (expected recency, dirs or size)",
+                    other
+                ));
+            }
+        }
+    }
+    Ok(weights)
+}
+
+// Function to parse a --newer-than / --older-than duration: a whole number followed by
+// s, m, h, d or w (seconds, minutes, hours, days, weeks)
+fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    let invalid = || format!("invalid duration '{}' (expected e.g. 30m, 12h, 7d)", value);
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let seconds_per_unit = match unit.to_ascii_lowercase() {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        'w' => 604_800,
+        _ => return Err(invalid()),
+    };
+    let count: u64 = value[..value.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    count
+        .checked_mul(seconds_per_unit)
+        .map(std::time::Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+// Function to parse --max-depth, which has to be at least 1 (depth 0 would list nothing)
+fn parse_max_depth(value: &str) -> Result<usize, String> {
+    match value.trim().parse::<usize>() {
+        Ok(0) => Err("the depth must be at least 1".to_string()),
+        Ok(depth) => Ok(depth),
+        Err(_) => Err(format!(
+            "invalid depth '{}' (expected a whole number)",
+            value
+        )),
+    }
+}
+
+// Function to parse a human-friendly size like "10M", "1.5G", "500K" or "1024" into bytes
+// Units are binary (1K = 1024 bytes) to match format_size, and a trailing "B" is optional
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let upper = value.to_ascii_uppercase();
+    let number = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, multiplier) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1024u64),
+        Some('M') => (&number[..number.len() - 1], 1024u64.pow(2)),
+        Some('G') => (&number[..number.len() - 1], 1024u64.pow(3)),
+        Some('T') => (&number[..number.len() - 1], 1024u64.pow(4)),
+        _ => (number, 1),
+    };
+    let amount: f64 = number.trim().parse().map_err(|_| {
+        format!(
+            "invalid size '{}' (expected e.g. 1024, 500K, 10M, 1.5G)",
+            value
+        )
+    })?;
+    if !amount.is_finite() || amount < 0.0 {
+        return Err(format!("invalid size '{}'", value));
+    }
+    Ok((amount * multiplier as f64).round() as u64)
+}
+
+// Function to render the attribute column, picking the compact Windows form for --compact-attrs
+fn format_attributes(entry: &Entry, arg: &Arg) -> String {
+    if cfg!(target_os = "windows") && arg.compact_attrs {
+        compact_attributes(entry.attribute)
+    } else {
+        let mut attributes = parse_attributes(entry.attribute, entry.kind);
+        attributes.extend(xattr_indicator(&entry.path));
+        attributes
+    }
+}
+
+// Function to pick the marker ls -l puts after the mode string from the file's
+// extended attributes (see xattr_marker); None when there are none (or off Linux)
+fn xattr_indicator(path: &Path) -> Option<char> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        // SAFETY: a zero size only asks for the length of the name list
+        let size = unsafe { ffi::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+        let mut names = vec![0u8; usize::try_from(size).ok().filter(|&size| size > 0)?];
+        // SAFETY: llistxattr writes at most names.len() bytes into the buffer
+        let len = unsafe { ffi::llistxattr(path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+        // The list can change between the calls; a failure still means it wasn't empty
+        names.truncate(usize::try_from(len).unwrap_or(0));
+        xattr_marker(&names)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+// Function to pick the marker for a NUL-separated list of xattr names, like GNU ls:
+// "+" for a POSIX ACL, "@" for any other attribute, "." for only an SELinux context.
+// Other security.* names (capabilities, IMA hashes) are set by the system, not the
+// user, so they get no marker; otherwise every file on an SELinux host would get one
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn xattr_marker(names: &[u8]) -> Option<char> {
+    let names: Vec<&[u8]> = names.split(|&b| b == 0).filter(|n| !n.is_empty()).collect();
+    if names
+        .iter()
+        .any(|name| name.starts_with(b"system.posix_acl_"))
+    {
+        Some('+')
+    } else if names.iter().any(|name| !name.starts_with(b"security.")) {
+        Some('@')
+    } else if names.contains(&&b"security.selinux"[..]) {
+        Some('.')
+    } else {
+        None
+    }
+}
+
+// Function to render Windows attributes as a fixed-width flag string like `attrib`:
+// R(eadonly) H(idden) S(ystem) A(rchive), with a dash for each unset bit
+fn compact_attributes(attr: u32) -> String {
+    [(0x1, 'R'), (0x2, 'H'), (0x4, 'S'), (0x20, 'A')]
+        .iter()
+        .map(|&(bit, flag)| if attr & bit != 0 { flag } else { '-' })
+        .collect()
+}
+
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn parse_attributes(attr: u32, kind: FileKind) -> String {
+    #[cfg(target_os = "windows")]
+    {
+        let mut attributes = Vec::new();
+
+        if attr & 0x1 != 0 {
+            attributes.push("READONLY");
+        }
+        if attr & 0x2 != 0 {
+            attributes.push("HIDDEN");
+        }
+        if attr & 0x4 != 0 {
+            attributes.push("SYSTEM");
+        }
+        if attr & 0x20 != 0 {
+            attributes.push("ARCHIVE");
+        }
+
+        if attributes.is_empty() {
+            String::from("NORMAL")
+        } else {
+            attributes.join(", ")
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        // Unix permissions (mode) - show like ls -l (e.g., -rw-r--r--, drwxr-xr-x)
+        let type_char = match kind {
+            FileKind::File => '-',
+            FileKind::Directory => 'd',
+            FileKind::Symlink => 'l',
+            FileKind::CharDevice => 'c',
+            FileKind::BlockDevice => 'b',
+            FileKind::Fifo => 'p',
+            FileKind::Socket => 's',
+        };
+        let mut mode = String::from(type_char);
+        // Owner, group, other; setuid, setgid and sticky show in the execute slot,
+        // lowercase when the execute bit is also set and uppercase when it isn't
+        for (shift, special_bit, special_char) in
+            [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')]
+        {
+            let bits = (attr >> shift) & 0o7;
+            mode.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+            mode.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+            mode.push(match (bits & 0o1 != 0, attr & special_bit != 0) {
+                (true, true) => special_char,
+                (false, true) => special_char.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            });
+        }
+        mode
+    }
+
+    #[cfg(not(any(unix, target_os = "windows")))]
+    {
+        String::from("UNKNOWN")
+    }
+}
+
+// Struct to hold file entry information
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    path: PathBuf,
+    modified: SystemTime,
+    accessed: Option<SystemTime>, // None where the platform or filesystem doesn't record it
+    created: Option<SystemTime>,  // the same
+    size: u64,
+    attribute: u32,
+    kind: FileKind,
+    depth: usize, // 1 for the listed directory's own children, 2 for theirs, ...
+    link_target: Option<PathBuf>, // symlinks only
+    broken: bool, // a symlink whose target doesn't exist
+    #[cfg(unix)]
+    uid: u32, // owner, shown by name in the long format
+    #[cfg(unix)]
+    gid: u32, // group, shown by name in the long format
+    #[cfg(unix)]
+    inode: u64, // for {inode} in --format-string
+    #[cfg(unix)]
+    nlink: u64, // hard link count, for --sort links
+    #[cfg(target_os = "macos")]
+    flags: u32, // BSD file flags (st_flags), used for Finder's hidden flag
+}
+
+// What kind of filesystem object an entry is (symlinks are not followed)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(unix), allow(dead_code))]
+enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,  // Unix only, like the ones in /dev
+    BlockDevice, // Unix only
+    Fifo,        // Unix only (named pipe)
+    Socket,      // Unix only
+}
+
+// Struct to hold a parsed --ignore glob
+#[derive(Debug, Clone)]
+struct Glob {
+    tokens: Vec<GlobToken>,
+    dir_only: bool,    // the pattern ended in "/"
+    ignore_case: bool, // --ignore-case
+}
+
+#[derive(Debug, Clone)]
+enum GlobToken {
+    Literal(char),
+    AnyChar, // ?
+    AnyRun,  // *
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>, // single characters are (c, c)
+    },
+}
+
+impl Glob {
+    fn matches(&self, name: &str, is_dir: bool) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        (is_dir || !self.dir_only) && glob_tokens_match(&self.tokens, &name, self.ignore_case)
+    }
+}
+
+// Struct to hold a parsed --format-string template
+#[derive(Debug, Clone)]
+struct Template(Vec<TemplatePart>);
+
+#[derive(Debug, Clone)]
+enum TemplatePart {
+    Literal(String),
+    Field {
+        field: TemplateField,
+        align: Option<(char, usize)>, // alignment ('<', '>' or '^') and width
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TemplateField {
+    Name,
+    Path,
+    Size,
+    Mtime,
+    Perms,
+    Owner,
+    Inode,
+}
+
+// Struct to hold the text of each long-format column, in display order
+struct LongColumns<'a> {
+    name: &'a str,
+    name_color: Option<Color>,
+    link: Option<&'a str>, // --hyperlink target for the name, e.g. "file:///home/a/notes.txt"
+    name_suffix: &'a str,  // text after the colored name, e.g. " -> target"
+    target_color: Option<Color>, // color for the target in a " -> target" suffix
+    attributes: &'a str,
+    color_permissions: bool, // attributes is a Unix "-rwxr-xr-x" string to color bit by bit
+    owner: Option<(&'a str, &'a str)>, // (user, group); None on Windows
+    size: &'a str,
+    size_bytes: Option<u64>, // the size behind `size`, to shade it; None for the title row
+    modified: &'a str,
+    age: Option<&'a str>,          // --relative-mtime, e.g. "3 days ago"
+    capabilities: Option<&'a str>, // --capabilities, e.g. "cap_net_raw=ep", printed last
+}
+
+// Struct to hold the column widths pinned with --columns (None = default width)
+#[derive(Debug, Clone, Copy, Default)]
+struct ColumnWidths {
+    name: Option<usize>,
+    size: Option<usize>,
+    mtime: Option<usize>,
+    attributes: Option<usize>,
+    owner: Option<usize>,
+    group: Option<usize>,
+}
+
+// Struct to hold the --relevance-weights for --sort relevance
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RelevanceWeights {
+    recency: f64,
+    dirs: f64,
+    size: f64,
+}
+
+impl Default for RelevanceWeights {
+    fn default() -> Self {
+        RelevanceWeights {
+            recency: 1.0,
+            dirs: 2.0,
+            size: 0.25,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build an Arg with every option at its default, as if `vw` was run with no flags
+    fn default_arg() -> Arg {
+        Arg::parse_from(["vw"])
+    }
+
+    // Build a plain entry with just a name and size, modified now
+    fn entry(name: &str, size: u64) -> Entry {
+        Entry {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            modified: SystemTime::now(),
+            accessed: None,
+            created: None,
+            size,
+            attribute: 0,
+            kind: FileKind::File,
+            depth: 1,
+            link_target: None,
+            broken: false,
+            #[cfg(unix)]
+            uid: 0,
+            #[cfg(unix)]
+            gid: 0,
+            #[cfg(unix)]
+            inode: 0,
+            #[cfg(unix)]
+            nlink: 1,
+            #[cfg(target_os = "macos")]
+            flags: 0,
+        }
+    }
+
+    // Create an empty scratch directory under the system temp dir for a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vw-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_summary_counts_and_total() {
+        let mut src = entry("src", 4096);
+        src.kind = FileKind::Directory;
+        let mut docs = entry("docs", 4096);
+        docs.kind = FileKind::Directory;
+        let entries = vec![
+            src,
+            docs,
+            entry("main.rs", 3000),
+            entry("lib.rs", 1000),
+            entry("Cargo.toml", 400),
+        ];
+        assert_eq!(
+            summary_line(&entries, &default_arg()),
+            "2 directories, 3 files, 4400B total"
+        );
+        let arg = Arg {
+            human_readable: true,
+            ..default_arg()
+        };
+        assert_eq!(
+            summary_line(&entries[1..3], &arg),
+            "1 directory, 1 file, 2.9K total"
+        );
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(500), "500B");
+        assert_eq!(format_size(2048), "2.0K");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0M");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0G");
+    }
+
+    #[test]
+    fn test_sort_by_name() {
+        let entries = vec![entry("zebra", 100), entry("apple", 200)];
+        let arg = default_arg();
+        let sorted = sort_entries(entries, &arg, &mut Warnings::default());
+        assert_eq!(sorted[0].name, "apple");
+        assert_eq!(sorted[1].name, "zebra");
+    }
+
+    #[test]
+    fn test_sort_by_size() {
+        let entries = vec![entry("small", 100), entry("large", 1000)];
+        let arg = Arg {
+            sort_by_size: true,
+            ..default_arg()
+        };
+        let sorted = sort_entries(entries, &arg, &mut Warnings::default());
+        assert_eq!(sorted[0].name, "large"); // Largest first
+        assert_eq!(sorted[1].name, "small");
+    }
+
+    #[test]
+    fn test_sort_key_variants() {
+        let sorted_names = |sort: SortKey, reverse: bool| {
+            let mut old = entry("b.txt", 300);
+            old.modified = SystemTime::UNIX_EPOCH;
+            let entries = vec![old, entry("c.md", 100), entry("a.txt", 200), entry("d", 50)];
+            let arg = Arg {
+                sort: Some(sort),
+                reverse,
+                ..default_arg()
+            };
+            sort_entries(entries, &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sorted_names(SortKey::Name, false),
+            ["a.txt", "b.txt", "c.md", "d"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::Name, true),
+            ["d", "c.md", "b.txt", "a.txt"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::Size, false),
+            ["b.txt", "a.txt", "c.md", "d"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::Size, true),
+            ["d", "c.md", "a.txt", "b.txt"]
+        );
+        assert_eq!(sorted_names(SortKey::Time, false)[3], "b.txt");
+        assert_eq!(sorted_names(SortKey::Time, true)[0], "b.txt");
+        assert_eq!(
+            sorted_names(SortKey::Extension, false),
+            ["d", "c.md", "a.txt", "b.txt"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::Extension, true),
+            ["b.txt", "a.txt", "c.md", "d"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::None, false),
+            ["b.txt", "c.md", "a.txt", "d"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::None, true),
+            ["d", "a.txt", "c.md", "b.txt"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_name_length() {
+        let entries = || {
+            vec![
+                entry("main.rs", 0),
+                entry("lib.rs", 0),
+                entry("README.md", 0),
+                entry("crème.rs", 0), // 8 characters, 9 bytes
+                entry("build.rs", 0),
+                entry("a", 0),
+            ]
+        };
+        let sorted_names = |reverse: bool| {
+            let arg = Arg {
+                sort: Some(SortKey::Length),
+                reverse,
+                ..default_arg()
+            };
+            sort_entries(entries(), &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sorted_names(false),
+            [
+                "a",
+                "lib.rs",
+                "main.rs",
+                "build.rs",
+                "crème.rs",
+                "README.md"
+            ]
+        );
+        assert_eq!(
+            sorted_names(true),
+            [
+                "README.md",
+                "crème.rs",
+                "build.rs",
+                "main.rs",
+                "lib.rs",
+                "a"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sort_by_owner_and_group() {
+        // uid/gid 0 resolve to a name; 4000000 falls back to the number, which sorts first
+        let owned = |name: &str, uid: u32, gid: u32| Entry {
+            uid,
+            gid,
+            ..entry(name, 0)
+        };
+        let entries = || {
+            vec![
+                owned("c.txt", 0, 4_000_000),
+                owned("b.txt", 4_000_000, 0),
+                owned("a.txt", 0, 0),
+            ]
+        };
+        let sorted_names = |sort: SortKey| {
+            let arg = Arg {
+                sort: Some(sort),
+                ..default_arg()
+            };
+            sort_entries(entries(), &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(user_name(4_000_000), "4000000");
+        assert_eq!(sorted_names(SortKey::Owner), ["b.txt", "a.txt", "c.txt"]);
+        assert_eq!(sorted_names(SortKey::Group), ["c.txt", "a.txt", "b.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sort_by_link_count() {
+        let root = temp_dir("sort-links");
+        std::fs::write(root.join("shared"), "").unwrap();
+        std::fs::hard_link(root.join("shared"), root.join("copy1")).unwrap();
+        std::fs::hard_link(root.join("shared"), root.join("copy2")).unwrap();
+        std::fs::write(root.join("alone"), "").unwrap();
+
+        let sorted_names = |reverse: bool| {
+            let arg = Arg {
+                sort: Some(SortKey::Links),
+                reverse,
+                ..default_arg()
+            };
+            let entries =
+                collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+            sort_entries(entries, &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| (e.name, e.nlink))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sorted_names(false),
+            [
+                ("alone".to_string(), 1),
+                ("copy1".to_string(), 3),
+                ("copy2".to_string(), 3),
+                ("shared".to_string(), 3)
+            ]
+        );
+        assert_eq!(sorted_names(true)[0].0, "shared");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_version_sort_orders_numbers_by_value() {
+        let entries = vec![
+            entry("frame120.png", 0),
+            entry("Frame2.png", 0),
+            entry("frame10.png", 0),
+            entry("frame1.png", 0),
+            entry("v2/", 0),
+            entry("v10/", 0),
+            entry("v1.9", 0),
+        ];
+        let arg = Arg {
+            version_sort: true,
+            ..default_arg()
+        };
+        let names: Vec<String> = sort_entries(entries, &arg, &mut Warnings::default())
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "frame1.png",
+                "Frame2.png",
+                "frame10.png",
+                "frame120.png",
+                "v1.9",
+                "v2/",
+                "v10/"
+            ]
+        );
+        assert!(natural_sort_key("a99999999999999999999999") > natural_sort_key("a007"));
+    }
+
+    #[test]
+    fn test_sort_by_embedded_size() {
+        let entries = vec![
+            entry("backup-2GB.tar", 0),
+            entry("notes.txt", 0),
+            entry("backup-500MB.tar", 0),
+            entry("backup-1.5G.tar", 0),
+            entry("backup-750k.tar", 0),
+            entry("v2-5Mbps.log", 0),
+        ];
+        let arg = Arg {
+            sort: Some(SortKey::EmbeddedSize),
+            ..default_arg()
+        };
+        let names: Vec<String> = sort_entries(entries, &arg, &mut Warnings::default())
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "backup-750k.tar",
+                "backup-500MB.tar",
+                "backup-1.5G.tar",
+                "backup-2GB.tar",
+                "notes.txt",
+                "v2-5Mbps.log"
+            ]
+        );
+        assert_eq!(embedded_size("disk-64B.img"), Some(64));
+        assert_eq!(embedded_size("build-42.log"), None);
+    }
+
+    #[test]
+    fn test_sort_by_created_time() {
+        let at = |seconds: u64| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        let made = |name: &str, created: Option<u64>, modified: u64| {
+            let mut e = entry(name, 0);
+            e.created = created.map(at);
+            e.modified = at(modified);
+            e
+        };
+        let entries = || {
+            vec![
+                made("old-but-edited", Some(100), 900),
+                made("new", Some(500), 600),
+                made("unknown", None, 300),
+            ]
+        };
+        let names = |args: &[&str]| {
+            let arg = Arg::parse_from(["vw"].iter().chain(args));
+            sort_entries(entries(), &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&["-t"]), ["old-but-edited", "new", "unknown"]);
+        // Without a creation time, "unknown" sorts by its mtime
+        assert_eq!(
+            names(&["-t", "--time", "created"]),
+            ["new", "unknown", "old-but-edited"]
+        );
+
+        let arg = Arg::parse_from(["vw", "-l", "--time", "created"]);
+        let lines = format_entries(entries(), &arg);
+        assert!(!lines[0].contains('*'));
+        assert!(lines[2].ends_with('*'), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_time_selector_drives_display_and_sort() {
+        let at = |seconds: u64| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        let file = |name: &str, accessed: u64, modified: u64| {
+            let mut e = entry(name, 0);
+            e.accessed = Some(at(accessed));
+            e.modified = at(modified);
+            e
+        };
+        let entries = || {
+            vec![
+                file("read-lately", 9_000_000, 100),
+                file("edited-lately", 200, 8_000_000),
+            ]
+        };
+        let arg = Arg::parse_from(["vw", "-t", "-l", "--time", "accessed"]);
+        let sorted = sort_entries(entries(), &arg, &mut Warnings::default());
+        assert_eq!(sorted[0].name, "read-lately");
+        let shown: DateTime<Local> = at(9_000_000).into();
+        let line = &format_entries(sorted, &arg)[0];
+        assert!(
+            line.ends_with(&shown.format("%b %d %H:%M").to_string()),
+            "{}",
+            line
+        );
+
+        let arg = Arg::parse_from(["vw", "-t", "-l"]);
+        let sorted = sort_entries(entries(), &arg, &mut Warnings::default());
+        assert_eq!(sorted[0].name, "edited-lately");
+        let shown: DateTime<Local> = at(8_000_000).into();
+        assert!(format_entries(sorted, &arg)[0].contains(&shown.format("%b %d %H:%M").to_string()));
+    }
+
+    #[test]
+    fn test_sort_by_relevance() {
+        let now = SystemTime::now();
+        let made = |name: &str, kind: FileKind, hours_ago: u64, size: u64| {
+            let mut e = entry(name, size);
+            e.kind = kind;
+            e.modified = now - std::time::Duration::from_secs(hours_ago * 3600);
+            e
+        };
+        let entries = || {
+            vec![
+                made("old.log", FileKind::File, 24 * 365, 1 << 30),
+                made("main.rs", FileKind::File, 1, 2000),
+                made("docs", FileKind::Directory, 24 * 30, 4096),
+                made("lib.rs", FileKind::File, 48, 8000),
+                made("src", FileKind::Directory, 2, 4096),
+            ]
+        };
+        let names = |args: &[&str]| {
+            let arg = Arg::parse_from(["vw", "--sort", "relevance"].iter().chain(args));
+            sort_entries(entries(), &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>()
+        };
+        // Directories first, then by recency; size only nudges
+        assert_eq!(names(&[]), ["src", "docs", "main.rs", "lib.rs", "old.log"]);
+        // Weighting size heavily brings the huge old log up among the files
+        assert_eq!(
+            names(&["--relevance-weights", "dirs:0,size:5"]),
+            ["old.log", "src", "main.rs", "lib.rs", "docs"]
+        );
+
+        assert_eq!(
+            parse_relevance_weights("size:1"),
+            Ok(RelevanceWeights {
+                size: 1.0,
+                ..RelevanceWeights::default()
+            })
+        );
+        assert!(parse_relevance_weights("age:1").is_err());
+        assert!(parse_relevance_weights("dirs:lots").is_err());
+    }
+
+    #[test]
+    fn test_date_buckets() {
+        use chrono::TimeZone;
+
+        // Thursday 2024-05-16, so the week started on Monday the 13th
+        let now = Local.with_ymd_and_hms(2024, 5, 16, 12, 0, 0).unwrap();
+        let at = |month, day| -> SystemTime {
+            Local
+                .with_ymd_and_hms(2024, month, day, 9, 30, 0)
+                .unwrap()
+                .into()
+        };
+        assert_eq!(date_bucket(at(5, 16), now), DateBucket::Today);
+        assert_eq!(date_bucket(at(5, 15), now), DateBucket::Yesterday);
+        assert_eq!(date_bucket(at(5, 13), now), DateBucket::ThisWeek);
+        assert_eq!(date_bucket(at(5, 12), now), DateBucket::ThisMonth);
+        assert_eq!(date_bucket(at(1, 2), now), DateBucket::ThisYear);
+        assert_eq!(date_bucket(SystemTime::UNIX_EPOCH, now), DateBucket::Older);
+    }
+
+    #[test]
+    fn test_sort_by_date_bucket() {
+        let mut ancient = entry("ancient", 0);
+        ancient.modified = SystemTime::UNIX_EPOCH;
+        let mut old = entry("old", 0);
+        old.modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(86_400);
+        let entries = vec![ancient, entry("recent", 0), old];
+        let arg = Arg {
+            sort: Some(SortKey::DateBucket),
+            ..default_arg()
+        };
+        let names: Vec<String> = sort_entries(entries, &arg, &mut Warnings::default())
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["recent", "old", "ancient"]);
+    }
+
+    #[test]
+    fn test_sort_shorthands_set_sort_key() {
+        assert_eq!(sort_key(&default_arg()), SortKey::Name);
+        assert_eq!(sort_key(&Arg::parse_from(["vw", "-t"])), SortKey::Time);
+        assert_eq!(sort_key(&Arg::parse_from(["vw", "-S"])), SortKey::Size);
+        assert_eq!(
+            sort_key(&Arg::parse_from(["vw", "--sort", "extension"])),
+            SortKey::Extension
+        );
+        assert!(Arg::try_parse_from(["vw", "-t", "--sort", "size"]).is_err());
+        assert!(Arg::try_parse_from(["vw", "-t", "-S"]).is_err());
+    }
+
+    #[test]
+    fn test_reverse_flips_time_and_size_sorts() {
+        let sorted_names = |flags: &[&str]| {
+            let mut old_big = entry("old_big", 900);
+            old_big.modified = SystemTime::UNIX_EPOCH;
+            let entries = vec![old_big, entry("new_small", 10)];
+            let arg = Arg::parse_from(std::iter::once("vw").chain(flags.iter().copied()));
+            sort_entries(entries, &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted_names(&["-t"]), ["new_small", "old_big"]); // newest first
+        assert_eq!(sorted_names(&["-t", "-r"]), ["old_big", "new_small"]); // oldest first
+        assert_eq!(sorted_names(&["-S"]), ["old_big", "new_small"]); // largest first
+        assert_eq!(sorted_names(&["-S", "-r"]), ["new_small", "old_big"]); // smallest first
+    }
+
+    #[test]
+    fn test_group_directories_first_under_each_sort() {
+        let entries = || {
+            let mut small_dir = entry("b_dir", 10);
+            small_dir.kind = FileKind::Directory;
+            let mut big_dir = entry("d_dir", 500);
+            big_dir.kind = FileKind::Directory;
+            big_dir.modified = SystemTime::UNIX_EPOCH;
+            vec![entry("a.txt", 300), small_dir, entry("c.md", 100), big_dir]
+        };
+        let sorted_names = |sort: SortKey, reverse: bool| {
+            let arg = Arg {
+                group_directories_first: true,
+                sort: Some(sort),
+                reverse,
+                ..default_arg()
+            };
+            sort_entries(entries(), &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sorted_names(SortKey::Name, false),
+            ["b_dir", "d_dir", "a.txt", "c.md"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::Name, true),
+            ["d_dir", "b_dir", "c.md", "a.txt"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::Size, false),
+            ["d_dir", "b_dir", "a.txt", "c.md"]
+        );
+        assert_eq!(sorted_names(SortKey::Time, false)[..2], ["b_dir", "d_dir"]);
+        assert_eq!(
+            sorted_names(SortKey::Extension, false),
+            ["b_dir", "d_dir", "c.md", "a.txt"]
+        );
+        assert_eq!(
+            sorted_names(SortKey::None, false),
+            ["b_dir", "d_dir", "a.txt", "c.md"]
+        );
+    }
+
+    #[test]
+    fn test_dotfiles_sort_before_letters() {
+        let entries = vec![entry("z", 0), entry(".z", 0), entry("a", 0), entry(".a", 0)];
+        let arg = Arg {
+            all: true,
+            ..default_arg()
+        };
+        let names: Vec<String> = sort_entries(entries, &arg, &mut Warnings::default())
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, [".a", ".z", "a", "z"]);
+    }
+
+    #[test]
+    fn test_reverse_sort() {
+        let entries = vec![entry("a", 100), entry("z", 200)];
+        let arg = Arg {
+            reverse: true,
+            ..default_arg()
+        };
+        let sorted = sort_entries(entries, &arg, &mut Warnings::default());
+        assert_eq!(sorted[0].name, "z");
+        assert_eq!(sorted[1].name, "a");
+    }
+
+    #[test]
+    fn test_should_display_filters_hidden() {
+        let entries = vec![entry(".hidden", 100), entry("visible", 200)];
+        let arg = default_arg();
+        let filtered = should_display(entries, &arg);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].name, "visible");
+    }
+
+    #[test]
+    fn test_ignore_globs() {
+        let mut target = entry("target", 0);
+        target.kind = FileKind::Directory;
+        let mut nested = entry("build.log", 0);
+        nested.path = PathBuf::from("target/debug/build.log");
+        nested.depth = 3;
+        let entries = vec![
+            entry("main.o", 0),
+            entry("notes.tmp", 0),
+            entry("target", 0), // a file, so 'target/' leaves it alone
+            target,
+            nested,
+            entry("file1.txt", 0),
+            entry("fileA.txt", 0),
+            entry("keep.rs", 0),
+        ];
+        let arg = Arg::parse_from([
+            "vw",
+            "-a",
+            "--ignore",
+            "*.o",
+            "--ignore",
+            "*.tmp",
+            "--ignore",
+            "target/",
+            "--ignore",
+            "file[0-9].txt",
+        ]);
+        let names: Vec<String> = should_display(entries, &arg)
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["target", "fileA.txt", "keep.rs"]);
+
+        let glob = parse_glob("[!a-c]?[]x]*").unwrap();
+        assert!(glob.matches("d1]", false));
+        assert!(glob.matches("zzx.rs", false));
+        assert!(!glob.matches("a1x", false));
+        assert!(!glob.matches("d1y", false));
+        assert!(parse_glob("[oops").unwrap().matches("[oops", false));
+        assert!(parse_glob("**.rs").unwrap().matches("main.rs", false));
+        assert!(parse_glob("a*b*c").unwrap().matches("aXbYbZc", false));
+        assert!(!parse_glob("a*b*c").unwrap().matches("aXbYc_", false));
+        // Many stars against a long near-miss would take forever with naive backtracking
+        let many_stars = parse_glob(&format!("{}*b", "*a".repeat(20))).unwrap();
+        assert!(!many_stars.matches(&"a".repeat(60), false));
+        assert!(many_stars.matches(&("a".repeat(60) + "b"), false));
+    }
+
+    #[test]
+    fn test_ignore_case_matches_mixed_case_names() {
+        let entries = || {
+            vec![
+                entry("IMG_01.JPG", 0),
+                entry("img_02.jpg", 0),
+                entry("Scan.JpG", 0),
+                entry("notes.txt", 0),
+            ]
+        };
+        let shown = |arg: &Arg| -> Vec<String> {
+            should_display(entries(), arg)
+                .into_iter()
+                .map(|e| e.name)
+                .collect()
+        };
+        let mut arg = Arg::parse_from(["vw", "--ignore", "*.jpg", "--ignore", "[a-z]*.TXT"]);
+        assert_eq!(shown(&arg), ["IMG_01.JPG", "Scan.JpG", "notes.txt"]);
+        arg.ignore_case = true;
+        for glob in &mut arg.ignore {
+            glob.ignore_case = true;
+        }
+        assert!(shown(&arg).is_empty());
+        assert!(Arg::try_parse_from(["vw", "--ignore", "/"]).is_err());
+    }
+
+    #[test]
+    fn test_global_ignore_file_hides_matches() {
+        let root = temp_dir("global-ignore");
+        let file = root.join("ignore");
+        std::fs::write(&file, "# build output\n*.o\n\nnode_modules/\n").unwrap();
+        let mut modules = entry("node_modules", 0);
+        modules.kind = FileKind::Directory;
+        let entries = vec![entry("main.o", 0), modules, entry("main.c", 0)];
+
+        let mut arg = Arg::parse_from(["vw", "--ignore", "*.c"]);
+        arg.ignore.extend(read_glob_file(&file));
+        assert_eq!(arg.ignore.len(), 3);
+        assert!(should_display(entries, &arg).is_empty());
+        assert!(read_glob_file(&root.join("missing")).is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_should_display_shows_all() {
+        let entries = vec![entry(".hidden", 100), entry("visible", 200)];
+        let arg = Arg {
+            all: true,
+            ..default_arg()
+        };
+        let filtered = should_display(entries, &arg);
+        assert_eq!(filtered.len(), 2);
+    }
+
+    #[test]
+    fn test_format_entries_short() {
+        let entries = vec![entry("test.txt", 1024)];
+        let arg = default_arg();
+        let formatted = format_entries(entries, &arg);
+        assert_eq!(formatted[0], "test.txt");
+    }
+
+    #[test]
+    fn test_classify_suffixes() {
+        let with_kind = |name: &str, kind: FileKind| {
+            let mut e = entry(name, 0);
+            e.kind = kind;
+            e
+        };
+        let mut script = entry("run.sh", 0);
+        script.attribute = 0o755;
+        let entries = || {
+            vec![
+                with_kind("src", FileKind::Directory),
+                with_kind("latest", FileKind::Symlink),
+                with_kind("pipe", FileKind::Fifo),
+                with_kind("socket", FileKind::Socket),
+                with_kind("tty", FileKind::CharDevice),
+                entry("notes.txt", 0),
+                entry("my notes", 0),
+            ]
+        };
+        let arg = Arg {
+            classify: true,
+            shell_quote: true,
+            ..default_arg()
+        };
+        assert_eq!(
+            format_entries(entries(), &arg),
+            [
+                "src/",
+                "latest@",
+                "pipe|",
+                "socket=",
+                "tty",
+                "notes.txt",
+                "'my notes'"
+            ]
+        );
+        let executable = if cfg!(unix) { "run.sh*" } else { "run.sh" };
+        assert_eq!(format_entries(vec![script], &arg), [executable]);
+
+        // Without -F names are left as they are, directories included
+        let arg = default_arg();
+        assert_eq!(format_entries(entries(), &arg)[0], "src");
+    }
+
+    #[test]
+    fn test_format_entries_with_human_readable() {
+        let entries = vec![entry("test.txt", 2048)];
+        let arg = Arg {
+            long_format: true,
+            human_readable: true,
+            ..default_arg()
+        };
+        let formatted = format_entries(entries, &arg);
+        assert!(formatted[0].contains("2.0K"));
+    }
+
+    #[test]
+    fn test_limit_output_bytes_truncates_with_notice() {
+        let entries: Vec<String> = (0..10).map(|i| format!("file{}.txt", i)).collect();
+        let arg = Arg {
+            max_output_bytes: Some(30),
+            ..default_arg()
+        };
+        let limited = limit_output_bytes(
+            entries,
+            " ",
+            "entries",
+            &arg,
+            &mut arg.max_output_bytes.clone(),
+        );
+        let (notice, kept) = limited.split_last().unwrap();
+        assert!(kept.join(" ").len() <= 30);
+        assert_eq!(kept.len(), 3);
+        assert_eq!(notice, "... output truncated at 30 bytes (7 more entries)");
+    }
+
+    #[test]
+    fn test_limit_output_bytes_keeps_everything_under_budget() {
+        let entries = vec!["a".to_string(), "b".to_string()];
+        let arg = Arg {
+            max_output_bytes: Some(100),
+            ..default_arg()
+        };
+        let limited = limit_output_bytes(
+            entries,
+            " ",
+            "entries",
+            &arg,
+            &mut arg.max_output_bytes.clone(),
+        );
+        assert_eq!(limited, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_max_output_bytes_is_shared_across_paths() {
+        let roots: Vec<PathBuf> = (0..3)
+            .map(|i| {
+                let root = temp_dir(&format!("budget-{}", i));
+                std::fs::write(root.join("a.txt"), "").unwrap();
+                std::fs::write(root.join("b.txt"), "").unwrap();
+                root
+            })
+            .collect();
+        let paths: Vec<String> = roots.iter().map(|r| r.display().to_string()).collect();
+        let listing = |arg: &Arg, path: &str, budget: &mut Option<usize>| {
+            let format = OutputFormat::SingleColumn;
+            let mut seen = HashSet::new();
+            path_listing(
+                path,
+                arg,
+                format,
+                &mut seen,
+                &mut Warnings::default(),
+                budget,
+            )
+            .unwrap()
+        };
+
+        // Enough for the whole first path and the second one's header and first entry;
+        // each path also prints a blank line after its listing
+        let first = listing(&default_arg(), &paths[0], &mut None);
+        assert_eq!(first, format!("{}:\na.txt\nb.txt", paths[0]));
+        let max = first.len() + 2 + paths[1].len() + 2 + "a.txt".len() + 2;
+        let arg = Arg {
+            max_output_bytes: Some(max),
+            ..default_arg()
+        };
+        let mut budget = arg.max_output_bytes;
+        assert_eq!(listing(&arg, &paths[0], &mut budget), first);
+        assert_eq!(budget, Some(max - first.len() - 2));
+        assert_eq!(
+            listing(&arg, &paths[1], &mut budget),
+            format!(
+                "{}:\na.txt\n... output truncated at {} bytes (1 more entries)",
+                paths[1], max
+            )
+        );
+        // Once it runs out, main stops before the third path
+        assert_eq!(budget, Some(0));
+
+        // The --summary footer counts too, and is dropped when it doesn't fit
+        let arg = Arg {
+            summary: true,
+            max_output_bytes: Some(first.len() + 2 + 4),
+            ..default_arg()
+        };
+        let mut budget = arg.max_output_bytes;
+        assert_eq!(
+            listing(&arg, &paths[2], &mut budget),
+            first.replace(&paths[0], &paths[2])
+        );
+        assert_eq!(budget, Some(0));
+        for root in roots {
+            std::fs::remove_dir_all(root).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_max_output_bytes_caps_the_laid_out_grid() {
+        let names: Vec<String> = ["a", "bb", "ccc", "dd", "e", "ffff"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let arg = Arg {
+            max_output_bytes: Some(12),
+            ..default_arg()
+        };
+        // Only the first of the two rows fits in 12 bytes
+        assert_eq!(grid_layout(&names, 13), "a   ccc  e\nbb  dd   ffff");
+        assert_eq!(
+            grid_output(&names, 13, &arg, &mut arg.max_output_bytes.clone()),
+            "a   ccc  e\n... output truncated at 12 bytes (1 more rows)"
+        );
+        assert_eq!(
+            grid_output(&names, 13, &default_arg(), &mut None),
+            grid_layout(&names, 13)
+        );
+    }
+
+    #[test]
+    fn test_parse_column_widths() {
+        let widths = parse_column_widths("name:30,size:12,mtime:20").unwrap();
+        assert_eq!(widths.name, Some(30));
+        assert_eq!(widths.size, Some(12));
+        assert_eq!(widths.mtime, Some(20));
+        assert_eq!(widths.attributes, None);
+        assert_eq!(parse_column_widths("owner:10").unwrap().owner, Some(10));
+        assert!(parse_column_widths("inode:10").is_err());
+        assert!(parse_column_widths("name:wide").is_err());
+    }
+
+    #[test]
+    fn test_format_entries_with_fixed_columns() {
+        let entries = vec![entry("a_really_long_file_name.txt", 2048)];
+        let arg = Arg {
+            long_format: true,
+            columns: Some(parse_column_widths("name:8,size:12").unwrap()),
+            ..default_arg()
+        };
+        let formatted = format_entries(entries, &arg);
+        assert!(formatted[0].starts_with("a_really  "));
+        assert!(formatted[0].contains(&format!("  {:>12}  ", "2048B")));
+    }
+
+    #[test]
+    fn test_flush_warnings_holds_back_for_errors_at_end() {
+        let mut warnings = Warnings::default();
+        warnings.push("permission denied".to_string());
+        let arg = Arg {
+            errors_at_end: true,
+            ..default_arg()
+        };
+        flush_warnings(&mut warnings, &arg);
+        assert_eq!(warnings.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_warning_summary_format() {
+        let warnings = vec![
+            "IO error for operation on ./a: Permission denied".to_string(),
+            "IO error for operation on ./b: Permission denied".to_string(),
+        ];
+        assert_eq!(
+            warning_summary(&warnings),
+            "2 warning(s):\n  Warning: IO error for operation on ./a: Permission denied\n  Warning: IO error for operation on ./b: Permission denied\n"
+        );
+    }
+
+    #[test]
+    fn test_find_dirs_reports_every_match() {
+        let root = temp_dir("find-dirs");
+        std::fs::create_dir_all(root.join("utils")).unwrap();
+        std::fs::create_dir_all(root.join("src/core/utils")).unwrap();
+        std::fs::write(root.join("src/utils"), "not a directory").unwrap();
+
+        let arg = Arg {
+            recursive: true,
+            ..default_arg()
+        };
+        let entries =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+        let found = find_dirs(&entries, "utils");
+
+        assert_eq!(
+            found,
+            vec![
+                root.join("src/core/utils").display().to_string(),
+                root.join("utils").display().to_string(),
+            ]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("plain.txt"), "plain.txt");
+        assert_eq!(shell_quote("src/"), "src/");
+        assert_eq!(shell_quote("my file.txt"), "'my file.txt'");
+        assert_eq!(shell_quote("it's.txt"), "'it'\\''s.txt'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+    }
+
+    #[test]
+    fn test_format_entries_shell_quoted() {
+        let entries = vec![entry("a b", 0), entry("c", 0)];
+        let arg = Arg {
+            shell_quote: true,
+            ..default_arg()
+        };
+        assert_eq!(format_entries(entries, &arg), vec!["'a b'", "c"]);
+    }
+
+    #[test]
+    fn test_has_visible_entry() {
+        let root = temp_dir("exists");
+        std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+        for i in 0..200 {
+            std::fs::write(root.join(format!("a/b/.cache{}", i)), "").unwrap();
+        }
+        let root_path = root.to_str().unwrap();
+
+        let arg = Arg {
+            recursive: true,
+            ..default_arg()
+        };
+        // The visible directories count as a match even though every file is hidden
+        assert!(has_visible_entry(root_path, &arg));
+        std::fs::remove_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join(".env"), "").unwrap(); // Only a hidden file left
+        assert!(!has_visible_entry(root_path, &arg));
+
+        let arg = Arg {
+            recursive: true,
+            all: true,
+            ..default_arg()
+        };
+        assert!(has_visible_entry(root_path, &arg));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_has_visible_entry_stops_at_first_match() {
+        let root = temp_dir("exists-early");
+        std::fs::write(root.join("found.env"), "").unwrap();
+        let arg = Arg {
+            recursive: true,
+            ..default_arg()
+        };
+        // Anything the walk would yield after the match fails the test if it's pulled
+        let walk = walk_entries(root.to_str().unwrap(), &arg)
+            .chain(std::iter::from_fn(|| panic!("walked past the first match")));
+        assert!(any_visible(walk, &arg));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_dot_hidden_file_hides_listed_entries() {
+        let root = temp_dir("dot-hidden");
+        std::fs::write(root.join("secret.txt"), "").unwrap();
+        std::fs::write(root.join("shown.txt"), "").unwrap();
+        std::fs::write(root.join(".hidden"), "secret.txt\n").unwrap();
+
+        let arg = default_arg();
+        let entries =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+        let names: Vec<String> = should_display(entries, &arg)
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, vec!["shown.txt"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_format_entries_with_template() {
+        let entries = vec![entry("notes.txt", 2048)];
+        let arg = Arg {
+            format_string: Some(parse_template("[{size:>6}] {name:<12}|{{done}}").unwrap()),
+            ..default_arg()
+        };
+        let formatted = format_entries(entries, &arg);
+        assert_eq!(formatted[0], "[  2048] notes.txt   |{done}");
+
+        let notes = Entry {
+            #[cfg(unix)]
+            inode: 4242,
+            ..entry("notes.txt", 0)
+        };
+        let arg = Arg {
+            format_string: Some(parse_template("{inode:>6} {owner} {name}").unwrap()),
+            ..default_arg()
+        };
+        let formatted = format_entries(vec![notes], &arg);
+        #[cfg(unix)]
+        assert_eq!(formatted[0], format!("  4242 {} notes.txt", user_name(0)));
+        #[cfg(not(unix))]
+        assert_eq!(formatted[0], "     - - notes.txt");
+
+        // {mtime} shows the --time field, marked "*" when it falls back to mtime
+        let day = std::time::Duration::from_secs(86_400);
+        let notes = Entry {
+            modified: SystemTime::UNIX_EPOCH + day * 100,
+            accessed: Some(SystemTime::UNIX_EPOCH + day * 200),
+            ..entry("notes.txt", 0)
+        };
+        let stamp = |time: SystemTime| {
+            DateTime::<Local>::from(time)
+                .format("%b %d %H:%M")
+                .to_string()
+        };
+        let mtime = |time: &str| {
+            let arg = Arg::parse_from(["vw", "--format-string", "{mtime}", "--time", time]);
+            render_template(
+                arg.format_string.as_ref().unwrap(),
+                &notes,
+                "notes.txt",
+                &arg,
+            )
+        };
+        assert_eq!(mtime("modified"), stamp(notes.modified));
+        assert_eq!(mtime("accessed"), stamp(notes.accessed.unwrap()));
+        assert_eq!(mtime("created"), format!("{}*", stamp(notes.modified)));
+    }
+
+    #[test]
+    fn test_parse_template_rejects_bad_placeholders() {
+        assert!(parse_template("{color}").is_err());
+        assert!(parse_template("{size:>wide}").is_err());
+        assert!(parse_template("{name").is_err());
+        assert!(parse_template("name}").is_err());
+    }
+
+    #[test]
+    fn test_overview_expands_directories() {
+        let root = temp_dir("overview");
+        std::fs::create_dir(root.join("docs")).unwrap();
+        for name in ["a.md", "b.md", "c.md", "d.md"] {
+            std::fs::write(root.join("docs").join(name), "").unwrap();
+        }
+        std::fs::write(root.join("readme.txt"), "").unwrap();
+
+        let arg = Arg {
+            overview: Some(2),
+            ..default_arg()
+        };
+        let mut warnings = Warnings::default();
+        let entries = collect_entries(root.to_str().unwrap(), &arg, &mut warnings).unwrap();
+        let lines = overview_lines(
+            sort_entries(entries, &arg, &mut Warnings::default()),
+            2,
+            &arg,
+            &mut warnings,
+        );
+
+        assert_eq!(
+            lines,
+            vec![
+                "docs",
+                "    a.md",
+                "    b.md",
+                "    ... 2 more",
+                "readme.txt"
+            ]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_sort_ignoring_articles() {
+        let entries = vec![
+            entry("The Matrix", 0),
+            entry("Avatar", 0),
+            entry("A Beautiful Mind", 0),
+        ];
+        let arg = Arg {
+            ignore_articles: true,
+            ..default_arg()
+        };
+        let names: Vec<String> = sort_entries(entries, &arg, &mut Warnings::default())
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, vec!["Avatar", "A Beautiful Mind", "The Matrix"]);
+    }
+
+    #[test]
+    fn test_sort_with_custom_articles() {
+        let entries = vec![entry("Das Zug", 0), entry("Le Avion", 0)];
+        let arg = Arg {
+            ignore_articles: true,
+            articles: vec!["le".to_string(), "das".to_string()],
+            ..default_arg()
+        };
+        let names: Vec<String> = sort_entries(entries, &arg, &mut Warnings::default())
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, vec!["Le Avion", "Das Zug"]);
+    }
+
+    #[test]
+    fn test_dot_graph_nodes_and_edges() {
+        let root = temp_dir("dot");
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "").unwrap();
+        std::fs::write(root.join("README.md"), "").unwrap();
+        let root_path = root.to_str().unwrap();
+
+        let arg = Arg {
+            recursive: true,
+            dot: true,
+            ..default_arg()
+        };
+        let entries = collect_entries(root_path, &arg, &mut Warnings::default()).unwrap();
+        let graph = dot_graph(root_path, &entries);
+
+        assert!(graph.starts_with(&format!("digraph \"{}\" {{", root_path)));
+        assert!(graph.ends_with('}'));
+        assert_eq!(graph.matches(" -> ").count(), 3);
+        assert_eq!(graph.matches("shape=box").count(), 2);
+        assert_eq!(graph.matches("shape=ellipse").count(), 2);
+        assert!(graph.contains(&format!(
+            "\"{}\" -> \"{}\";",
+            root.join("src").display(),
+            root.join("src/main.rs").display()
+        )));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_dot_quote_escapes() {
+        assert_eq!(dot_quote(r#"say "hi"\"#), r#""say \"hi\"\\""#);
+    }
+
+    #[test]
+    fn test_warn_large_dir() {
+        let root = temp_dir("large-dir");
+        std::fs::create_dir(root.join("crowded")).unwrap();
+        std::fs::create_dir(root.join("quiet")).unwrap();
+        for i in 0..25 {
+            std::fs::write(root.join("crowded").join(format!("f{}", i)), "").unwrap();
+        }
+        std::fs::write(root.join("quiet/only"), "").unwrap();
+
+        let arg = Arg {
+            recursive: true,
+            warn_large_dir: Some(20),
+            ..default_arg()
+        };
+        let mut warnings = Warnings::default();
+        collect_entries(root.to_str().unwrap(), &arg, &mut warnings).unwrap();
+        assert_eq!(
+            warnings.messages,
+            vec![format!(
+                "{} has more than 20 entries",
+                root.join("crowded").display()
+            )]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_default_format_depends_on_tty() {
+        let arg = default_arg();
+        assert_eq!(output_format(&arg, true), OutputFormat::Grid);
+        assert_eq!(output_format(&arg, false), OutputFormat::SingleColumn);
+
+        let arg = Arg {
+            format: Some(OutputFormat::Line),
+            ..default_arg()
+        };
+        assert_eq!(output_format(&arg, true), OutputFormat::Line);
+
+        let arg = Arg {
+            long_format: true,
+            ..default_arg()
+        };
+        assert_eq!(output_format(&arg, true), OutputFormat::SingleColumn);
+    }
+
+    #[test]
+    fn test_grid_layout_fills_columns_first() {
+        let names: Vec<String> = ["a", "bb", "ccc", "dd", "e"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        assert_eq!(grid_layout(&names, 12), "a   ccc  e\nbb  dd");
+        assert_eq!(grid_layout(&names, 80), "a  bb  ccc  dd  e");
+        assert_eq!(grid_layout(&names, 1), "a\nbb\nccc\ndd\ne");
+        assert_eq!(grid_layout(&[], 80), "");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("2K"), Ok(2048));
+        assert_eq!(parse_size("10M"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1.5G"), Ok(1536 * 1024 * 1024));
+        assert_eq!(parse_size("500kb"), Ok(500 * 1024));
+        assert_eq!(parse_size("12B"), Ok(12));
+        assert!(parse_size("big").is_err());
+        assert!(parse_size("-1K").is_err());
+    }
+
+    #[test]
+    fn test_size_range_filters_files() {
+        let mut dir = entry("assets", 4096);
+        dir.kind = FileKind::Directory;
+        let entries = vec![
+            entry("tiny.txt", 100),
+            entry("medium.bin", 2048),
+            entry("large.iso", 20 << 20),
+            dir,
+        ];
+        let arg = Arg::parse_from(["vw", "--min-size", "1K", "--max-size", "10M"]);
+        assert_eq!(check_size_range(&arg), Ok(()));
+        let names: Vec<String> = should_display(entries, &arg)
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["medium.bin", "assets"]);
+
+        let arg = Arg::parse_from(["vw", "--min-size", "2M", "--max-size", "500K"]);
+        assert!(check_size_range(&arg).is_err());
+        assert!(Arg::try_parse_from(["vw", "--min-size", "lots"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let hours = |count: u64| std::time::Duration::from_secs(count * 3600);
+        assert_eq!(parse_duration("12h"), Ok(hours(12)));
+        assert_eq!(parse_duration("7d"), Ok(hours(7 * 24)));
+        assert_eq!(parse_duration("2w"), Ok(hours(14 * 24)));
+        assert_eq!(
+            parse_duration("30m"),
+            Ok(std::time::Duration::from_secs(1800))
+        );
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("1.5h").is_err());
+    }
+
+    #[test]
+    fn test_time_window_filters_by_age() {
+        let now = SystemTime::now();
+        let aged = |name: &str, hours: u64| {
+            let mut e = entry(name, 0);
+            e.modified = now - std::time::Duration::from_secs(hours * 3600);
+            e
+        };
+        let mut skewed = entry("future.txt", 0);
+        skewed.modified = now + std::time::Duration::from_secs(3600);
+        let entries = [
+            aged("fresh.txt", 2),
+            aged("week.txt", 30),
+            aged("old.txt", 24 * 40),
+            skewed,
+        ];
+        let names = |args: &[&str]| {
+            let arg = Arg::parse_from(["vw"].iter().chain(args));
+            entries
+                .iter()
+                .filter(|e| in_time_window(e, &arg, now))
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&["--newer-than", "1d"]), ["fresh.txt", "future.txt"]);
+        assert_eq!(names(&["--older-than", "7d"]), ["old.txt"]);
+        assert_eq!(
+            names(&["--newer-than", "2w", "--older-than", "1d"]),
+            ["week.txt"]
+        );
+    }
+
+    #[test]
+    fn test_exclude_larger_than_skips_big_files() {
+        let root = temp_dir("exclude-larger");
+        std::fs::create_dir(root.join("nested")).unwrap();
+        std::fs::write(root.join("small.conf"), "x").unwrap();
+        std::fs::write(root.join("nested/huge.bin"), vec![0u8; 4096]).unwrap();
+
+        let arg = Arg {
+            recursive: true,
+            exclude_larger_than: Some(parse_size("1K").unwrap()),
+            ..default_arg()
+        };
+        let mut names: Vec<String> =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default())
+                .unwrap()
+                .into_iter()
+                .map(|e| e.name)
+                .collect();
+        names.sort();
+        assert_eq!(names, vec!["nested", "small.conf"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path("../sibling/./thing"),
+            PathBuf::from("../sibling/thing")
+        );
+        assert_eq!(normalize_path("./src/../docs/"), PathBuf::from("docs"));
+        assert_eq!(normalize_path("a/.."), PathBuf::from("."));
+        assert_eq!(normalize_path("../../x"), PathBuf::from("../../x"));
+        assert_eq!(normalize_path("."), PathBuf::from("."));
+        #[cfg(unix)]
+        assert_eq!(normalize_path("/../etc/./"), PathBuf::from("/etc"));
+    }
+
+    #[test]
+    fn test_canonicalize_resolves_against_filesystem() {
+        let root = temp_dir("canonicalize");
+        std::fs::create_dir(root.join("inner")).unwrap();
+        let messy = format!("{}/inner/../inner/.", root.display());
+
+        let arg = Arg {
+            canonicalize: true,
+            ..default_arg()
+        };
+        let expected = std::fs::canonicalize(root.join("inner")).unwrap();
+        assert_eq!(
+            resolve_path(&messy, &arg).unwrap(),
+            expected.display().to_string()
+        );
+        assert!(resolve_path(&format!("{}/missing", root.display()), &arg).is_err());
+
+        let logical = resolve_path(&messy, &default_arg()).unwrap();
+        assert_eq!(logical, root.join("inner").display().to_string());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_dedup_overlapping_paths() {
+        let root = temp_dir("dedup");
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("a/b/deep.txt"), "").unwrap();
+        std::fs::write(root.join("a/top.txt"), "").unwrap();
+        let outer = root.join("a").display().to_string();
+        let inner = root.join("a/b").display().to_string();
+
+        let arg = Arg {
+            recursive: true,
+            dedup: true,
+            ..default_arg()
+        };
+        let mut seen = HashSet::new();
+        let first = collect_entries(&outer, &arg, &mut Warnings::default()).unwrap();
+        let first = dedup_entries(first, &outer, &arg, &mut seen);
+        let second = collect_entries(&inner, &arg, &mut Warnings::default()).unwrap();
+        let second = dedup_entries(second, &inner, &arg, &mut seen);
+
+        assert_eq!(first.len(), 3);
+        assert!(second.is_empty());
+        assert_eq!(
+            overlap_warnings(&[outer.clone(), inner.clone()]),
+            vec![format!(
+                "{} overlaps {}, its entries will be listed twice (use --dedup)",
+                inner, outer
+            )]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_long_format_columns_without_labels() {
+        use chrono::TimeZone;
+
+        let mut notes = entry("notes.txt", 2048);
+        notes.modified = Local.with_ymd_and_hms(2024, 3, 5, 14, 7, 0).unwrap().into();
+        let arg = Arg {
+            long_format: true,
+            human_readable: true,
+            ..default_arg()
+        };
+        let formatted = format_entries(vec![notes], &arg);
+        let attributes = parse_attributes(0, FileKind::File);
+        #[cfg(unix)]
+        let attributes = format!(
+            "{:<11}  {:<8}  {:<8}",
+            attributes,
+            user_name(0),
+            group_name(0)
+        );
+        #[cfg(not(unix))]
+        let attributes = format!("{:<10}", attributes);
+        assert_eq!(
+            formatted[0],
+            format!(
+                "{:<20}  {}  {:>10}  Mar 05 14:07",
+                "notes.txt", attributes, "2.0K"
+            )
+        );
+    }
+
+    #[test]
+    fn test_relative_to_names_in_long_format() {
+        let mut file = entry("main.rs", 10);
+        file.path = PathBuf::from("/work/project/src/main.rs");
+        let mut dir = entry("bin", 0);
+        dir.path = PathBuf::from("/work/project/src/bin");
+        let arg = Arg {
+            long_format: true,
+            relative_to: Some(PathBuf::from("/work/project")),
+            ..default_arg()
+        };
+        let formatted = format_entries(vec![file, dir], &arg);
+        assert!(formatted[0].starts_with("src/main.rs "), "{}", formatted[0]);
+        assert!(formatted[1].starts_with("src/bin "), "{}", formatted[1]);
+
+        assert_eq!(
+            relative_path(Path::new("/work/other/a.txt"), Path::new("/work/project/")),
+            PathBuf::from("../other/a.txt")
+        );
+        assert_eq!(
+            relative_path(Path::new("/work"), Path::new("/work")),
+            PathBuf::from(".")
+        );
+    }
+
+    #[test]
+    fn test_size_both_shows_bytes_and_human_size() {
+        let arg = Arg {
+            long_format: true,
+            size_both: true,
+            human_readable: true,
+            ..default_arg()
+        };
+        let formatted = format_entries(vec![entry("disk.img", 1024 * 1024)], &arg);
+        assert!(
+            formatted[0].contains("  1048576 (1.0M)  "),
+            "{}",
+            formatted[0]
+        );
+    }
+
     #[test]
-    fn test_sort_by_name() {
-        let entries = vec![
-            Entry {
-                name: "zebra".to_string(),
-                modified: SystemTime::now(),
-                size: 100,
-                attribute: 0,
+    fn test_header_row_lines_up_with_columns() {
+        let arg = Arg {
+            long_format: true,
+            header: true,
+            ..default_arg()
+        };
+        let header = long_format_line(
+            &LongColumns {
+                name: "NAME",
+                name_color: None,
+                link: None,
+                name_suffix: "",
+                target_color: None,
+                size: "SIZE",
+                size_bytes: None,
+                modified: "MODIFIED",
+                attributes: "PERMISSIONS",
+                color_permissions: false,
+                age: None,
+                capabilities: None,
+                owner: Some(("OWNER", "GROUP")),
             },
-            Entry {
-                name: "apple".to_string(),
-                modified: SystemTime::now(),
-                size: 200,
-                attribute: 0,
+            &arg,
+        );
+        let row = long_format_line(
+            &LongColumns {
+                name: "notes.txt",
+                name_color: None,
+                link: None,
+                name_suffix: "",
+                target_color: None,
+                size: "2048B",
+                size_bytes: Some(2048),
+                modified: "Mar 05 14:07",
+                attributes: "-rw-r--r--",
+                color_permissions: false,
+                age: None,
+                capabilities: None,
+                owner: Some(("aniket", "staff")),
             },
-        ];
+            &arg,
+        );
+        assert_eq!(
+            header.find("SIZE").unwrap() + 4,
+            row.find("2048B").unwrap() + 5
+        );
+        assert_eq!(header.find("MODIFIED"), row.find("Mar 05"));
+        assert_eq!(header.find("PERMISSIONS"), row.find("-rw-r--r--"));
+        assert_eq!(header.find("OWNER"), row.find("aniket"));
+        assert_eq!(header.find("GROUP"), row.find("staff"));
+        // Owner and group sit between the permissions and the size, like ls -l
+        assert!(header.find("PERMISSIONS") < header.find("OWNER"));
+        assert!(header.find("GROUP") < header.find("SIZE"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_permissions_as_rwx_string() {
+        assert_eq!(parse_attributes(0o644, FileKind::File), "-rw-r--r--");
+        assert_eq!(parse_attributes(0o755, FileKind::Directory), "drwxr-xr-x");
+        assert_eq!(parse_attributes(0o777, FileKind::Symlink), "lrwxrwxrwx");
+        assert_eq!(parse_attributes(0o660, FileKind::BlockDevice), "brw-rw----");
+        assert_eq!(parse_attributes(0o4755, FileKind::File), "-rwsr-xr-x");
+        assert_eq!(parse_attributes(0o2644, FileKind::File), "-rw-r-Sr--");
+        assert_eq!(parse_attributes(0o1777, FileKind::Directory), "drwxrwxrwt");
+        assert_eq!(parse_attributes(0o1644, FileKind::Fifo), "prw-r--r-T");
+    }
+
+    #[test]
+    fn test_no_color_and_color_when() {
+        use std::ffi::OsStr;
+
+        assert!(color_enabled(ColorWhen::Auto, None, true));
+        assert!(!color_enabled(ColorWhen::Auto, None, false));
+        assert!(!color_enabled(ColorWhen::Auto, Some(OsStr::new("1")), true));
+        assert!(!color_enabled(ColorWhen::Auto, Some(OsStr::new("")), true));
+        assert!(color_enabled(
+            ColorWhen::Always,
+            Some(OsStr::new("1")),
+            false
+        ));
+        assert!(!color_enabled(ColorWhen::Never, None, true));
+        assert_eq!(Arg::parse_from(["vw"]).color, ColorWhen::Auto);
+        assert_eq!(
+            Arg::parse_from(["vw", "--color", "never"]).color,
+            ColorWhen::Never
+        );
+    }
+
+    #[test]
+    fn test_relative_age() {
+        let now = SystemTime::now();
+        let ago = |seconds: u64| relative_age(now - std::time::Duration::from_secs(seconds), now);
+        assert_eq!(ago(3 * 86_400 + 500), "3 days ago");
+        assert_eq!(ago(20), "just now");
+        assert_eq!(ago(60), "1 minute ago");
+        assert_eq!(ago(5 * 3600), "5 hours ago");
+        assert_eq!(ago(400 * 86_400), "1 year ago");
+        assert_eq!(
+            relative_age(now + std::time::Duration::from_secs(60), now),
+            "in the future"
+        );
+
+        let mut old = entry("old.log", 10);
+        old.modified = now - std::time::Duration::from_secs(2 * 3600);
+        let arg = Arg::parse_from(["vw", "-l", "--relative-mtime"]);
+        let line = &format_entries(vec![old], &arg)[0];
+        assert!(line.ends_with("  2 hours ago"), "{}", line);
+    }
+
+    #[test]
+    fn test_parse_capabilities() {
+        let data = |words: &[u32]| {
+            words
+                .iter()
+                .flat_map(|w| w.to_le_bytes())
+                .collect::<Vec<u8>>()
+        };
+        // Revision 2 with the effective flag: cap_net_raw (bit 13) and cap_net_admin (bit 12)
+        let value = data(&[0x0200_0001, 1 << 12 | 1 << 13, 0, 0, 0]);
+        assert_eq!(
+            parse_capabilities(&value).as_deref(),
+            Some("cap_net_admin,cap_net_raw=ep")
+        );
+        // Revision 3 (with a root id), a high capability and one only inheritable
+        let value = data(&[0x0300_0000, 1, 1 << 5, 1 << 7, 0, 0]);
+        assert_eq!(
+            parse_capabilities(&value).as_deref(),
+            Some("cap_chown,cap_bpf=p cap_kill=i")
+        );
+        assert_eq!(parse_capabilities(&data(&[0x0200_0000, 0, 0, 0, 0])), None);
+        assert_eq!(parse_capabilities(&[1, 2]), None);
+    }
+
+    // Setting a capability needs CAP_SETFCAP and a filesystem with xattrs; skip without them
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_capabilities_in_long_format() {
+        unsafe extern "C" {
+            fn setxattr(
+                path: *const std::ffi::c_char,
+                name: *const std::ffi::c_char,
+                value: *const std::ffi::c_void,
+                size: usize,
+                flags: i32,
+            ) -> i32;
+        }
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = temp_dir("capabilities");
+        let binary = root.join("ping");
+        std::fs::write(&binary, "").unwrap();
+        let path = std::ffi::CString::new(binary.as_os_str().as_bytes()).unwrap();
+        let value: Vec<u8> = [0x0200_0001u32, 1 << 13, 0, 0, 0]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect();
+        // SAFETY: the path and name are NUL-terminated and value is a live buffer of its length
+        let set = unsafe {
+            setxattr(
+                path.as_ptr(),
+                c"security.capability".as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if set == 0 {
+            let arg = Arg::parse_from(["vw", "-l", "--capabilities"]);
+            let entries =
+                collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+            let line = &format_entries(entries, &arg)[0];
+            assert!(line.ends_with("  cap_net_raw=ep"), "{}", line);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_xattr_marker_by_name() {
+        assert_eq!(xattr_marker(b""), None);
+        assert_eq!(xattr_marker(b"user.test\0"), Some('@'));
+        assert_eq!(xattr_marker(b"security.selinux\0"), Some('.'));
+        assert_eq!(xattr_marker(b"security.capability\0"), None);
+        assert_eq!(xattr_marker(b"security.selinux\0user.test\0"), Some('@'));
+        assert_eq!(
+            xattr_marker(b"security.selinux\0system.posix_acl_access\0"),
+            Some('+')
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_xattr_indicator_in_long_format() {
+        unsafe extern "C" {
+            fn setxattr(
+                path: *const std::ffi::c_char,
+                name: *const std::ffi::c_char,
+                value: *const std::ffi::c_void,
+                size: usize,
+                flags: i32,
+            ) -> i32;
+        }
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = temp_dir("xattr_indicator");
+        std::fs::write(root.join("plain.txt"), "").unwrap();
+        let tagged = root.join("tagged.txt");
+        std::fs::write(&tagged, "").unwrap();
+        let path = std::ffi::CString::new(tagged.as_os_str().as_bytes()).unwrap();
+        // SAFETY: the path and name are NUL-terminated and value is a live buffer of its length
+        let set = unsafe {
+            setxattr(
+                path.as_ptr(),
+                c"user.test".as_ptr(),
+                b"1".as_ptr().cast(),
+                1,
+                0,
+            )
+        };
+        let arg = Arg::parse_from(["vw", "-l"]);
+        let entries =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+        // A plain file has no marker, or just "." for its context on an SELinux host
+        let plain = entries.iter().find(|e| e.name == "plain.txt").unwrap();
+        let attributes = format_attributes(plain, &arg);
+        assert!(
+            attributes.len() == 10 || attributes.ends_with('.'),
+            "{}",
+            attributes
+        );
+        if set == 0 {
+            let tagged = entries.iter().find(|e| e.name == "tagged.txt").unwrap();
+            let attributes = format_attributes(tagged, &arg);
+            assert!(attributes.starts_with("-rw") && attributes.ends_with('@'));
+            assert_eq!(attributes.len(), 11);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_paint_size_by_magnitude() {
+        let small = paint_size("      512B", 512, true);
+        let large = paint_size("      3.0G", 3 << 30, true);
+        assert_eq!(small, "      \x1b[2m512B\x1b[0m");
+        assert_eq!(large, "      \x1b[1;97m3.0G\x1b[0m");
+        assert!(paint_size("5.0M", 5 << 20, true).starts_with("\x1b[1m"));
+        assert_eq!(paint_size("4.0K", 4096, true), "4.0K");
+        assert_eq!(paint_size("3.0G", 3 << 30, false), "3.0G");
+    }
+
+    #[test]
+    fn test_paint_permissions_colors_each_bit() {
+        let painted = paint_permissions("-rwxrwxrwx ", true);
+        assert!(painted.starts_with("-\x1b[33mr\x1b[0m\x1b[31mw\x1b[0m\x1b[32mx\x1b[0m"));
+        assert!(painted.contains("\x1b[33mr\x1b[0m\x1b[1;31mw\x1b[0m\x1b[32mx\x1b[0m "));
+        assert_eq!(painted.matches("\x1b[33m").count(), 3);
+        assert_eq!(painted.matches("\x1b[32m").count(), 3);
+        assert_eq!(visible_width(&painted), 11);
+
+        let painted = paint_permissions("-rw-r-----", true);
+        assert!(painted.contains("\x1b[2m-\x1b[0m"));
+        assert!(!painted.contains("1;31"));
+        assert_eq!(paint_permissions("-rw-r-----", false), "-rw-r-----");
+    }
+
+    #[test]
+    fn test_ellipsize_long_owner_names() {
+        assert_eq!(ellipsize("CORP\\very.long.username", 10), "CORP\\very…");
+        assert_eq!(
+            ellipsize("CORP\\very.long.username", 10).chars().count(),
+            10
+        );
+        assert_eq!(ellipsize("staff", 10), "staff");
+        assert_eq!(ellipsize("staff", 5), "staff");
+        assert_eq!(ellipsize("staff", 1), "…");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_owner_names_fall_back_to_ids() {
+        assert_eq!(user_name(0), "root");
+        assert_eq!(user_name(3_999_999_999), "3999999999");
+        assert_eq!(group_name(3_999_999_999), "3999999999");
+    }
+
+    #[test]
+    fn test_warnings_count_by_kind() {
+        let mut warnings = Warnings::default();
+        warnings.count(Some(std::io::ErrorKind::PermissionDenied));
+        warnings.count(Some(std::io::ErrorKind::PermissionDenied));
+        warnings.count(Some(std::io::ErrorKind::NotFound));
+        warnings.count(Some(std::io::ErrorKind::Interrupted));
+        warnings.count(None);
+        assert_eq!(warnings.permission_denied, 2);
+        assert_eq!(warnings.not_found, 1);
+        assert_eq!(warnings.other_io, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_dir_counts_as_permission_error() {
+        let root = temp_dir("permission-denied");
+        let locked = root.join("locked");
+        std::fs::create_dir(&locked).unwrap();
+        std::fs::write(locked.join("secret"), "").unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+        // Root can read the directory anyway, so there's nothing to check
+        if std::fs::read_dir(&locked).is_err() {
+            let arg = Arg {
+                recursive: true,
+                ..default_arg()
+            };
+            let mut warnings = Warnings::default();
+            collect_entries(root.to_str().unwrap(), &arg, &mut warnings).unwrap();
+            assert_eq!(warnings.permission_denied, 1);
+            assert_eq!(warnings.messages.len(), 1);
+        }
+
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_compact_attributes() {
+        assert_eq!(compact_attributes(0x1 | 0x20), "R--A");
+        assert_eq!(compact_attributes(0x2 | 0x4), "-HS-");
+        assert_eq!(compact_attributes(0x80), "----"); // NORMAL
+        assert_eq!(compact_attributes(0x1 | 0x2 | 0x4 | 0x20), "RHSA");
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_long_format_compact_attrs_on_windows() {
+        let mut readonly = entry("readonly.txt", 10);
+        readonly.attribute = 0x1 | 0x20;
         let arg = Arg {
-            paths: vec![],
-            all: false,
-            recursive: false,
-            sort_by_time: false,
-            reverse: false,
-            sort_by_size: false,
-            long_format: false,
-            human_readable: false,
+            long_format: true,
+            compact_attrs: true,
+            ..default_arg()
         };
-        let sorted = sort_entries(entries, &arg);
-        assert_eq!(sorted[0].name, "apple");
-        assert_eq!(sorted[1].name, "zebra");
+        let formatted = format_entries(vec![readonly], &arg);
+        assert!(formatted[0].ends_with("R--A"));
     }
 
     #[test]
-    fn test_sort_by_size() {
-        let entries = vec![
-            Entry {
-                name: "small".to_string(),
-                modified: SystemTime::now(),
-                size: 100,
-                attribute: 0,
-            },
-            Entry {
-                name: "large".to_string(),
-                modified: SystemTime::now(),
-                size: 1000,
-                attribute: 0,
-            },
+    fn test_format_size_aligned_right_edges() {
+        let sizes = [
+            999,
+            1024,
+            12 * 1024 + 512,
+            1023 * 1024 * 1024,
+            3 * 1024 * 1024 * 1024,
         ];
+        let rendered: Vec<String> = sizes.iter().map(|&s| format_size_aligned(s)).collect();
+        assert_eq!(
+            rendered,
+            vec!["   999B", "   1.0K", "  12.5K", "1023.0M", "   3.0G"]
+        );
+        assert!(rendered.iter().all(|r| r.len() == 7));
+
+        // A narrow pinned size column keeps the size, not the alignment padding
+        assert_eq!(fit_column("   2.0K", Some(4), 10, true), "2.0K");
+        assert_eq!(fit_column("   2.0K", Some(6), 10, true), "  2.0K");
+        assert_eq!(fit_column("   2.0K", None, 10, true), "      2.0K");
         let arg = Arg {
-            paths: vec![],
-            all: false,
-            recursive: false,
-            sort_by_time: false,
-            reverse: false,
-            sort_by_size: true,
-            long_format: false,
-            human_readable: false,
+            long_format: true,
+            human_readable: true,
+            columns: Some(parse_column_widths("size:4").unwrap()),
+            ..default_arg()
         };
-        let sorted = sort_entries(entries, &arg);
-        assert_eq!(sorted[0].name, "large"); // Largest first
-        assert_eq!(sorted[1].name, "small");
+        let formatted = format_entries(vec![entry("notes.txt", 2048)], &arg);
+        assert!(formatted[0].contains("  2.0K  "), "{}", formatted[0]);
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_reverse_sort() {
-        let entries = vec![
-            Entry {
-                name: "a".to_string(),
-                modified: SystemTime::now(),
-                size: 100,
-                attribute: 0,
-            },
-            Entry {
-                name: "z".to_string(),
-                modified: SystemTime::now(),
-                size: 200,
-                attribute: 0,
-            },
-        ];
+    fn test_sort_key_exec() {
+        let root = temp_dir("sort-key-exec");
+        let script = root.join("key.sh");
+        // Key on whatever follows the first dash, so "z-1" sorts before "a-2"
+        std::fs::write(&script, "#!/bin/sh\necho \"${1#*-}\"\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let entries = vec![entry("a-2", 0), entry("z-1", 0), entry("m-3", 0)];
         let arg = Arg {
-            paths: vec![],
-            all: false,
-            recursive: false,
-            sort_by_time: false,
-            reverse: true,
-            sort_by_size: false,
-            long_format: false,
-            human_readable: false,
+            sort_key_exec: Some(script.display().to_string()),
+            ..default_arg()
         };
-        let sorted = sort_entries(entries, &arg);
-        assert_eq!(sorted[0].name, "z");
-        assert_eq!(sorted[1].name, "a");
+        let names: Vec<String> = sort_entries(entries, &arg, &mut Warnings::default())
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, vec!["z-1", "a-2", "m-3"]);
+
+        // A failing command is a warning per entry, and the entries fall back to name order
+        let arg = Arg {
+            sort_key_exec: Some("false".to_string()),
+            ..default_arg()
+        };
+        let mut warnings = Warnings::default();
+        let names: Vec<String> =
+            sort_entries(vec![entry("b", 0), entry("a", 0)], &arg, &mut warnings)
+                .into_iter()
+                .map(|e| e.name)
+                .collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(warnings.messages.len(), 2);
+        assert!(warnings.messages[0].starts_with("sort key command failed for b"));
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_should_display_filters_hidden() {
-        let entries = vec![
-            Entry {
-                name: ".hidden".to_string(),
-                modified: SystemTime::now(),
-                size: 100,
-                attribute: 0,
-            },
-            Entry {
-                name: "visible".to_string(),
-                modified: SystemTime::now(),
-                size: 200,
-                attribute: 0,
-            },
-        ];
+    fn test_walk_modes() {
+        let root = temp_dir("walk-modes");
+        std::fs::create_dir(root.join("real")).unwrap();
+        std::fs::write(root.join("real/file.txt"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("link")).unwrap();
+        let root_path = root.to_str().unwrap();
+        let link_path = root.join("link").display().to_string();
+
+        // Count how often file.txt turns up when walking `path` with the given mode
+        let count_files = |path: &str, walk: WalkMode| {
+            let arg = Arg {
+                recursive: true,
+                walk,
+                ..default_arg()
+            };
+            collect_entries(path, &arg, &mut Warnings::default())
+                .unwrap()
+                .iter()
+                .filter(|e| e.name == "file.txt")
+                .count()
+        };
+
+        assert_eq!(count_files(root_path, WalkMode::Physical), 1);
+        assert_eq!(count_files(root_path, WalkMode::Logical), 2);
+        assert_eq!(count_files(root_path, WalkMode::CommandLineLogical), 1);
+
+        assert_eq!(count_files(&link_path, WalkMode::Physical), 0);
+        assert_eq!(count_files(&link_path, WalkMode::Logical), 1);
+        assert_eq!(count_files(&link_path, WalkMode::CommandLineLogical), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_only_dirs() {
+        let root = temp_dir("follow-only-dirs");
+        std::fs::create_dir(root.join("real")).unwrap();
+        std::fs::write(root.join("real/file.txt"), "12345").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("dir-link")).unwrap();
+        std::os::unix::fs::symlink(root.join("real/file.txt"), root.join("file-link")).unwrap();
+
+        let arg = Arg::parse_from(["vw", "-R", "--follow-only-dirs"]);
+        let entries =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+        let files: Vec<_> = entries.iter().filter(|e| e.name == "file.txt").collect();
+        assert_eq!(files.len(), 2);
+        assert!(
+            files
+                .iter()
+                .any(|e| e.path.starts_with(root.join("dir-link")))
+        );
+        let file_link = entries.iter().find(|e| e.name == "file-link").unwrap();
+        assert_eq!(file_link.kind, FileKind::Symlink);
+        assert_ne!(file_link.size, 5);
+        assert!(Arg::try_parse_from(["vw", "--follow-only-dirs", "--walk", "logical"]).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_name_color_by_kind() {
+        let mut dir = entry("src", 0);
+        dir.kind = FileKind::Directory;
+        let mut link = entry("latest", 0);
+        link.kind = FileKind::Symlink;
+        let mut script = entry("build.sh", 0);
+        script.attribute = 0o755;
+        let plain = entry("notes.txt", 0);
+
+        assert_eq!(name_color(&dir), Some(Color::Blue));
+        assert_eq!(name_color(&link), Some(Color::Cyan));
+        assert_eq!(name_color(&plain), None);
+        if cfg!(unix) {
+            assert_eq!(name_color(&script), Some(Color::Green));
+        } else {
+            assert_eq!(name_color(&script), None);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collect_entries_records_kind() {
+        let root = temp_dir("kinds");
+        std::fs::create_dir(root.join("dir")).unwrap();
+        std::fs::write(root.join("file"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("file"), root.join("link")).unwrap();
+
+        let arg = default_arg();
+        let mut entries =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let kinds: Vec<FileKind> = entries.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![FileKind::Directory, FileKind::File, FileKind::Symlink]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hyperlinks_point_at_percent_encoded_file_urls() {
+        assert_eq!(
+            file_url(Path::new("/home/me/my notes/résumé.txt")),
+            "file:///home/me/my%20notes/r%C3%A9sum%C3%A9.txt"
+        );
+        let relative = file_url(Path::new("src"));
+        assert!(relative.starts_with("file:///") && relative.ends_with("/src"));
+
+        let linked = hyperlink("notes.txt", "file:///tmp/notes.txt");
+        assert_eq!(
+            linked,
+            "\x1b]8;;file:///tmp/notes.txt\x1b\\notes.txt\x1b]8;;\x1b\\"
+        );
+        assert_eq!(visible_width(&linked), 9);
+        assert_eq!(
+            paint_name_column("a.txt", None, "", None, Some("file:///a.txt"), Some(8)),
+            format!("{}   ", hyperlink("a.txt", "file:///a.txt"))
+        );
+    }
+
+    #[test]
+    fn test_visible_width_ignores_escape_codes() {
+        assert_eq!(visible_width("\x1b[34msrc/\x1b[0m"), 4);
+        assert_eq!(visible_width("plain"), 5);
+        let names = vec!["\x1b[34msrc/\x1b[0m".to_string(), "a".to_string()];
+        assert_eq!(grid_layout(&names, 80), "\x1b[34msrc/\x1b[0m  a");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_long_format_shows_symlink_target() {
+        let root = temp_dir("link-target");
+        std::fs::write(root.join("real.txt"), "").unwrap();
+        std::os::unix::fs::symlink("real.txt", root.join("link")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", root.join("dangling")).unwrap();
+
         let arg = Arg {
-            paths: vec![],
-            all: false,
-            recursive: false,
-            sort_by_time: false,
-            reverse: false,
-            sort_by_size: false,
-            long_format: false,
-            human_readable: false,
+            long_format: true,
+            ..default_arg()
         };
-        let filtered = should_display(entries, &arg);
-        assert_eq!(filtered.len(), 1);
-        assert_eq!(filtered[0].name, "visible");
+        let entries =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+        let formatted = format_entries(sort_entries(entries, &arg, &mut Warnings::default()), &arg);
+        assert!(formatted[0].starts_with("dangling -> missing.txt (broken) "));
+        assert!(formatted[1].starts_with("link -> real.txt"));
+        assert!(formatted[2].starts_with("real.txt  "));
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_should_display_shows_all() {
-        let entries = vec![
-            Entry {
-                name: ".hidden".to_string(),
-                modified: SystemTime::now(),
-                size: 100,
-                attribute: 0,
-            },
-            Entry {
-                name: "visible".to_string(),
-                modified: SystemTime::now(),
-                size: 200,
-                attribute: 0,
-            },
-        ];
+    fn test_broken_symlinks_are_kept_and_marked() {
+        let root = temp_dir("broken-link");
+        std::fs::write(root.join("real.txt"), "").unwrap();
+        std::os::unix::fs::symlink("real.txt", root.join("link")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", root.join("dangling")).unwrap();
+
+        // Followed or not, the dangling link stays in the listing without a warning
+        for walk in ["physical", "logical"] {
+            let arg = Arg::parse_from(["vw", "--walk", walk]);
+            let mut warnings = Warnings::default();
+            let entries = sort_entries(
+                collect_entries(root.to_str().unwrap(), &arg, &mut warnings).unwrap(),
+                &arg,
+                &mut Warnings::default(),
+            );
+            let marks: Vec<(&str, FileKind, bool)> = entries
+                .iter()
+                .map(|e| (e.name.as_str(), e.kind, e.broken))
+                .collect();
+            assert_eq!(marks[0], ("dangling", FileKind::Symlink, true), "{}", walk);
+            assert!(!entries[1].broken);
+            assert!(warnings.messages.is_empty(), "{}", walk);
+            assert_eq!(name_color(&entries[0]), Some(Color::Red));
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_targets_are_colored_by_type() {
+        let root = temp_dir("link-colors");
+        std::fs::create_dir(root.join("dir")).unwrap();
+        std::fs::write(root.join("plain.txt"), "").unwrap();
+        std::fs::write(root.join("run.sh"), "").unwrap();
+        std::fs::set_permissions(
+            root.join("run.sh"),
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .unwrap();
+        for (link, target) in [
+            ("to-dir", "dir"),
+            ("to-plain", "plain.txt"),
+            ("to-script", "run.sh"),
+            ("dangling", "missing.txt"),
+        ] {
+            std::os::unix::fs::symlink(target, root.join(link)).unwrap();
+        }
+
+        let arg = default_arg();
+        let entries =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+        let colors = |name: &str| {
+            let link = entries.iter().find(|e| e.name == name).unwrap();
+            (name_color(link), link_target_color(link))
+        };
+        // The link itself stays cyan (red when broken); its target gets the target's color
+        assert_eq!(colors("to-dir"), (Some(Color::Cyan), Some(Color::Blue)));
+        assert_eq!(colors("to-script"), (Some(Color::Cyan), Some(Color::Green)));
+        assert_eq!(colors("to-plain"), (Some(Color::Cyan), None));
+        assert_eq!(colors("dangling"), (Some(Color::Red), None));
+        assert_eq!(colors("dir"), (Some(Color::Blue), None));
+
+        // Without colors the column is the same plain text as before
+        assert_eq!(
+            paint_name_column("to-dir", None, " -> dir", Some(Color::Blue), None, Some(8)),
+            "to-dir -"
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_symlink_target() {
+        let mut link = entry("link", 0);
+        link.kind = FileKind::Symlink;
+        assert_eq!(link_suffix(&link), " -> (unreadable)");
+        assert_eq!(link_suffix(&entry("file", 0)), "");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_error_on_loop_fails_the_run() {
+        let root = temp_dir("error-on-loop");
+        std::fs::create_dir(root.join("dir")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("dir/up")).unwrap();
+        let root_name = root.to_str().unwrap();
+
+        let arg = Arg::parse_from(["vw", "-R", "--walk", "logical", "--error-on-loop"]);
+        let mut warnings = Warnings::default();
+        collect_entries(root_name, &arg, &mut warnings).unwrap();
+        assert_eq!(warnings.loops, 1);
+        assert_eq!(
+            run_failure(&warnings, &arg),
+            Some("1 symlink loops skipped".to_string())
+        );
+
+        let arg = Arg::parse_from(["vw", "-R", "--walk", "logical"]);
+        assert_eq!(run_failure(&warnings, &arg), None);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_split_output_writes_a_file_per_path() {
+        let root = temp_dir("split-output");
+        std::fs::create_dir_all(root.join("alpha")).unwrap();
+        std::fs::write(root.join("alpha/a.txt"), "").unwrap();
+        std::fs::create_dir_all(root.join("beta")).unwrap();
+        std::fs::write(root.join("beta/b.txt"), "").unwrap();
+        let reports = root.join("reports");
+        let arg = default_arg();
+
+        let mut seen = HashSet::new();
+        let mut taken = HashSet::new();
+        let mut warnings = Warnings::default();
+        for (path, file) in [("alpha", "a.txt"), ("beta", "b.txt")] {
+            let path = root.join(path).display().to_string();
+            let written = write_split_listing(
+                &reports,
+                &path,
+                &arg,
+                OutputFormat::SingleColumn,
+                &mut seen,
+                &mut taken,
+                &mut warnings,
+            )
+            .unwrap();
+            assert_eq!(written, reports.join(split_output_name(&path)));
+            assert_eq!(
+                std::fs::read_to_string(&written).unwrap(),
+                format!("{}\n", file)
+            );
+        }
+        assert_eq!(std::fs::read_dir(&reports).unwrap().count(), 2);
+
+        assert_eq!(split_output_name("src/bin"), "src_bin.txt");
+        assert_eq!(split_output_name("/"), "root.txt");
+        assert_eq!(split_output_name("."), "current.txt");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_split_output_keeps_colliding_names_apart() {
+        let root = temp_dir("split-output-collision");
+        std::fs::create_dir_all(root.join("src/bin")).unwrap();
+        std::fs::write(root.join("src/bin/nested.rs"), "").unwrap();
+        std::fs::create_dir_all(root.join("src_bin")).unwrap();
+        std::fs::write(root.join("src_bin/flat.rs"), "").unwrap();
+        let reports = root.join("reports");
+        let arg = default_arg();
+
+        let mut seen = HashSet::new();
+        let mut taken = HashSet::new();
+        let mut warnings = Warnings::default();
+        // ".../src/bin" and ".../src_bin" both flatten to the same file name
+        let mut write = |path: &str| {
+            write_split_listing(
+                &reports,
+                &root.join(path).display().to_string(),
+                &arg,
+                OutputFormat::SingleColumn,
+                &mut seen,
+                &mut taken,
+                &mut warnings,
+            )
+            .unwrap()
+        };
+        let first = write("src/bin");
+        let second = write("src_bin");
+        let name = split_output_name(&root.join("src_bin").display().to_string());
+        assert_eq!(first, reports.join(&name));
+        assert_eq!(second, reports.join(name.replace(".txt", "-2.txt")));
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "nested.rs\n");
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), "flat.rs\n");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_interrupt_flips_the_flag() {
+        // A local flag, so walks in other tests aren't stopped by the global one
+        let flag = std::sync::atomic::AtomicBool::new(false);
+        assert!(
+            !note_interrupt(&flag),
+            "the first Ctrl-C only asks the walk to stop"
+        );
+        assert!(flag.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(note_interrupt(&flag), "a second Ctrl-C force-quits");
+        assert!(!interrupted());
+    }
+
+    #[test]
+    fn test_exit_code_reflects_errors() {
+        let arg = default_arg();
+        let mut warnings = Warnings::default();
+        assert_eq!(exit_code(&warnings, &arg), 0);
+
+        // Advice like overlap notes doesn't count as a problem
+        warnings.push("a overlaps b".to_string());
+        assert_eq!(exit_code(&warnings, &arg), 0);
+
+        warnings.count(Some(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(exit_code(&warnings, &arg), 1);
+
+        // A path that can't be read at all is an error, not a warning
+        let root = temp_dir("exit-code");
+        let missing = root.join("missing");
+        let error = collect_entries(missing.to_str().unwrap(), &arg, &mut warnings).unwrap_err();
+        assert!(format!("{:#}", error).contains("missing"));
+        warnings.fail_path(error);
+        assert_eq!(exit_code(&warnings, &arg), 2);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_gitignore_rules() {
+        let root = temp_dir("gitignore");
+        std::fs::create_dir_all(root.join(".git/info")).unwrap();
+        std::fs::write(root.join(".git/info/exclude"), "*.swp\n").unwrap();
+        std::fs::write(
+            root.join(".gitignore"),
+            "# build output\n*.log\n!keep.log\n/build/\ndocs/**/draft.md\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join("build")).unwrap();
+        std::fs::write(root.join("build/out.bin"), "").unwrap();
+        std::fs::create_dir_all(root.join("src/build")).unwrap(); // not at the top, so kept
+        std::fs::write(root.join("src/.gitignore"), "secret.txt\n").unwrap();
+        std::fs::write(root.join("src/secret.txt"), "").unwrap();
+        std::fs::write(root.join("src/main.rs"), "").unwrap();
+        std::fs::write(root.join("src/main.rs.swp"), "").unwrap();
+        std::fs::create_dir_all(root.join("docs/a/b")).unwrap();
+        std::fs::write(root.join("docs/a/b/draft.md"), "").unwrap();
+        std::fs::write(root.join("debug.log"), "").unwrap();
+        std::fs::write(root.join("keep.log"), "").unwrap();
+
+        // List from the subdirectory too, so the top-level .gitignore has to be found above it
+        let listed = |path: PathBuf| {
+            let arg = Arg::parse_from(["vw", "-R", "--gitignore"]);
+            let entries =
+                collect_entries(path.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+            let mut names: Vec<String> = should_display(entries, &arg)
+                .into_iter()
+                .map(|e| e.path.strip_prefix(&path).unwrap().display().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            listed(root.clone()),
+            [
+                "docs",
+                "docs/a",
+                "docs/a/b",
+                "keep.log",
+                "src",
+                "src/build",
+                "src/main.rs"
+            ]
+        );
+        assert_eq!(listed(root.join("src")), ["build", "main.rs"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_skip_hidden_dirs_prunes_walk() {
+        let root = temp_dir("skip-hidden-dirs");
+        std::fs::create_dir_all(root.join(".git/objects")).unwrap();
+        std::fs::write(root.join(".git/objects/pack"), "").unwrap();
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join("src/.env"), "").unwrap();
+
         let arg = Arg {
-            paths: vec![],
+            recursive: true,
             all: true,
-            recursive: false,
-            sort_by_time: false,
-            reverse: false,
-            sort_by_size: false,
-            long_format: false,
-            human_readable: false,
+            skip_hidden_dirs: true,
+            ..default_arg()
         };
-        let filtered = should_display(entries, &arg);
-        assert_eq!(filtered.len(), 2);
+        let mut names: Vec<String> =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default())
+                .unwrap()
+                .into_iter()
+                .map(|e| e.name)
+                .collect();
+        names.sort();
+        assert_eq!(names, vec![".env", ".git", "src"]);
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
     #[test]
-    fn test_format_entries_short() {
-        let entries = vec![Entry {
-            name: "test.txt".to_string(),
-            modified: SystemTime::now(),
-            size: 1024,
-            attribute: 0,
-        }];
+    fn test_stdin_paths_split_and_skip_blanks() {
+        assert_eq!(
+            stdin_paths("./src\n\n./docs\r\n  \nmy dir\n", false),
+            ["./src", "./docs", "my dir"]
+        );
+        assert_eq!(
+            stdin_paths("./a\0./line\nbreak\0\0", true),
+            ["./a", "./line\nbreak"]
+        );
+        assert!(Arg::try_parse_from(["vw", "--null"]).is_err());
+    }
+
+    #[test]
+    fn test_respect_markers_prunes_marked_directories() {
+        let root = temp_dir("respect-markers");
+        std::fs::create_dir_all(root.join("photos/thumbs")).unwrap();
+        std::fs::write(root.join("photos/thumbs/.nomedia"), "").unwrap();
+        std::fs::write(root.join("photos/thumbs/a.jpg"), "").unwrap();
+        std::fs::write(root.join("photos/b.jpg"), "").unwrap();
+        let root_name = root.to_str().unwrap();
+
+        let names = |args: &[&str]| {
+            let arg = Arg::parse_from(["vw", "-R", "-a"].iter().chain(args));
+            let mut names: Vec<String> = collect_entries(root_name, &arg, &mut Warnings::default())
+                .unwrap()
+                .into_iter()
+                .map(|e| e.name)
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&["--respect-markers"]), ["b.jpg", "photos"]);
+        assert_eq!(names(&["--respect-markers=.nobackup"]).len(), 5);
+
+        // A following path is a path, not the marker name
+        let arg = Arg::parse_from(["vw", "-R", "--respect-markers", "rv"]);
+        assert_eq!(arg.respect_markers.as_deref(), Some(".nomedia"));
+        assert_eq!(arg.paths, ["rv"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_max_depth_limits_recursion() {
+        let root = temp_dir("max-depth");
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("a/b/deep.txt"), "").unwrap();
+        std::fs::write(root.join("top.txt"), "").unwrap();
+        let root_name = root.to_str().unwrap();
+
+        let names = |flags: &[&str]| {
+            let arg = Arg::parse_from(std::iter::once("vw").chain(flags.iter().copied()));
+            let entries = collect_entries(root_name, &arg, &mut Warnings::default()).unwrap();
+            sort_entries(entries, &arg, &mut Warnings::default())
+                .into_iter()
+                .map(|e| e.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&["-R", "--max-depth", "1"]), names(&[]));
+        assert_eq!(names(&["-R", "--max-depth", "2"]), ["a", "b", "top.txt"]);
+        assert_eq!(names(&["-R"]).len(), 4);
+        assert!(Arg::try_parse_from(["vw", "-R", "--max-depth", "0"]).is_err());
+        assert!(Arg::try_parse_from(["vw", "--max-depth", "2"]).is_err()); // needs -R
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tree_draws_connectors() {
+        let root = temp_dir("tree");
+        std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+        std::fs::write(root.join("a/b/c/too-deep.txt"), "").unwrap();
+        std::fs::write(root.join("a/one.txt"), "").unwrap();
+        std::fs::write(root.join("a/.hidden"), "").unwrap();
+        std::fs::write(root.join("z.txt"), "").unwrap();
+        let root_name = root.to_str().unwrap();
+
+        let tree = |arg: &Arg| {
+            let entries = collect_entries(root_name, arg, &mut Warnings::default()).unwrap();
+            tree_lines(
+                root_name,
+                sort_entries(should_display(entries, arg), arg, &mut Warnings::default()),
+                arg,
+            )
+        };
+        let arg = Arg::parse_from(["vw", "-R", "--tree", "--max-depth", "3"]);
+        assert_eq!(
+            tree(&arg),
+            [
+                "├── a",
+                "│   ├── b",
+                "│   │   └── c",
+                "│   └── one.txt",
+                "└── z.txt",
+            ]
+        );
+        let arg = Arg::parse_from(["vw", "-R", "--tree", "-a", "--max-depth", "2"]);
+        assert_eq!(
+            tree(&arg),
+            [
+                "├── a",
+                "│   ├── .hidden",
+                "│   ├── b",
+                "│   └── one.txt",
+                "└── z.txt"
+            ]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tree_siblings_follow_the_sort() {
+        let root = temp_dir("tree-sort");
+        std::fs::create_dir(root.join("d")).unwrap();
+        let made = |name: &str, size: usize, seconds: u64| {
+            let path = root.join(name);
+            if size > 0 {
+                std::fs::write(&path, vec![b'x'; size]).unwrap();
+            }
+            let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            std::fs::File::open(&path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        made("old.txt", 300, 1000);
+        made("new.txt", 10, 3000);
+        made("d/a", 5, 10);
+        made("d/b", 50, 20);
+        made("d", 0, 2000); // after its children, which would bump its time
+        let root_name = root.to_str().unwrap();
+
+        let tree = |flags: &[&str]| {
+            let arg = Arg::parse_from(["vw", "-R", "--tree"].iter().chain(flags));
+            let entries = collect_entries(root_name, &arg, &mut Warnings::default()).unwrap();
+            tree_lines(
+                root_name,
+                sort_entries(
+                    should_display(entries, &arg),
+                    &arg,
+                    &mut Warnings::default(),
+                ),
+                &arg,
+            )
+        };
+        assert_eq!(
+            tree(&["-t"]),
+            [
+                "├── new.txt",
+                "├── d",
+                "│   ├── b",
+                "│   └── a",
+                "└── old.txt"
+            ]
+        );
+        assert_eq!(
+            tree(&["-t", "-r"]),
+            [
+                "├── old.txt",
+                "├── d",
+                "│   ├── a",
+                "│   └── b",
+                "└── new.txt"
+            ]
+        );
+        // With -S each level is ordered by size on its own; a directory's own size
+        // depends on the filesystem, so only the files' order is checked
+        let by_size = tree(&["-S"]);
+        let position = |line: &str| by_size.iter().position(|l| l.ends_with(line)).unwrap();
+        assert!(position("── b") < position("── a"));
+        assert!(position("── old.txt") < position("── new.txt"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_show_depth_prefixes_lines() {
+        let root = temp_dir("show-depth");
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::write(root.join("a/b/deep.txt"), "").unwrap();
+        std::fs::write(root.join("top.txt"), "").unwrap();
+
         let arg = Arg {
-            paths: vec![],
-            all: false,
-            recursive: false,
-            sort_by_time: false,
-            reverse: false,
-            sort_by_size: false,
-            long_format: false,
-            human_readable: false,
+            recursive: true,
+            show_depth: true,
+            ..default_arg()
         };
-        let formatted = format_entries(entries, &arg);
-        assert_eq!(formatted[0], "test.txt");
+        let entries =
+            collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default()).unwrap();
+        let mut lines = format_entries(sort_entries(entries, &arg, &mut Warnings::default()), &arg);
+        lines.sort();
+        assert_eq!(lines, ["1 a", "1 top.txt", "2 b", "3 deep.txt"]);
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
     #[test]
-    fn test_format_entries_with_human_readable() {
-        let entries = vec![Entry {
-            name: "test.txt".to_string(),
-            modified: SystemTime::now(),
-            size: 2048,
-            attribute: 0,
-        }];
-        let arg = Arg {
-            paths: vec![],
-            all: false,
-            recursive: false,
-            sort_by_time: false,
-            reverse: false,
-            sort_by_size: false,
-            long_format: true,
-            human_readable: true,
+    #[cfg(unix)]
+    fn test_special_files_are_never_read() {
+        let root = temp_dir("special");
+        let fifo = root.join("pipe");
+        let status = std::process::Command::new("mkfifo").arg(&fifo).status();
+        if !status.is_ok_and(|status| status.success()) {
+            return; // no mkfifo here
+        }
+        let _socket = std::os::unix::net::UnixListener::bind(root.join("sock")).unwrap();
+        std::fs::write(root.join("plain"), "x").unwrap();
+        let root_name = root.to_str().unwrap().to_string();
+
+        // Checksumming opens files, which would block forever on the FIFO
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let arg = Arg::parse_from(["vw", "-R", "--manifest", "m", "--checksum"]);
+            let entries = collect_entries(&root_name, &arg, &mut Warnings::default()).unwrap();
+            let kinds: Vec<FileKind> = sort_entries(entries, &arg, &mut Warnings::default())
+                .iter()
+                .map(|e| e.kind)
+                .collect();
+            let entries = collect_entries(&root_name, &arg, &mut Warnings::default()).unwrap();
+            let _ = sender.send((
+                kinds,
+                manifest_lines(entries, true, &mut Warnings::default()),
+            ));
+        });
+        let (kinds, manifest) = receiver
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("listing a FIFO blocked");
+        assert_eq!(kinds, [FileKind::Fifo, FileKind::File, FileKind::Socket]);
+        let checksums: Vec<&str> = manifest
+            .lines()
+            .map(|l| l.split('\t').nth(2).unwrap())
+            .collect();
+        assert_eq!(checksums, ["-", "8cdc1683", "-"]);
+
+        let arg = Arg::parse_from(["vw", "--no-special"]);
+        let entries = collect_entries(root.to_str().unwrap(), &arg, &mut Warnings::default());
+        let names: Vec<String> = should_display(entries.unwrap(), &arg)
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        assert_eq!(names, ["plain"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_lists_files_sorted_with_checksums() {
+        let root = temp_dir("manifest");
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/check.txt"), "123456789").unwrap();
+        std::fs::write(root.join("empty"), "").unwrap();
+        let root_name = root.to_str().unwrap();
+
+        let arg = Arg::parse_from(["vw", "-R", "--manifest", "out.txt", "--checksum"]);
+        let entries = collect_entries(root_name, &arg, &mut Warnings::default()).unwrap();
+        let files = entries
+            .into_iter()
+            .filter(|entry| entry.kind != FileKind::Directory)
+            .collect();
+        let manifest = manifest_lines(files, arg.checksum, &mut Warnings::default());
+        let lines: Vec<Vec<&str>> = manifest
+            .lines()
+            .map(|line| line.split('\t').collect())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][0], "0");
+        assert_eq!(lines[0][2], "00000000");
+        assert_eq!(lines[0][3], root.join("empty").display().to_string());
+        assert_eq!(lines[1][0], "9");
+        assert_eq!(lines[1][2], "cbf43926"); // the standard CRC-32 check value
+        assert_eq!(
+            lines[1][3],
+            root.join("sub/check.txt").display().to_string()
+        );
+        assert!(lines[1][1].parse::<u64>().unwrap() > 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_json_listing_is_keyed_by_path_and_plain() {
+        use chrono::TimeZone;
+
+        let mut dir = entry("src", 4096);
+        dir.kind = FileKind::Directory;
+        dir.attribute = 0o40755;
+        let mut file = entry("main.rs", 120);
+        file.modified = Local.with_ymd_and_hms(2024, 3, 5, 14, 7, 0).unwrap().into();
+        let object = json_entry(&file);
+        assert!(object.starts_with(
+            "{\"name\":\"main.rs\",\"path\":\"main.rs\",\"size\":120,\"modified\":\"2024-03-05T14:07:00"
+        ));
+        assert!(object.contains("\"is_dir\":false"));
+        assert!(json_entry(&dir).contains("\"name\":\"src\""));
+        assert!(json_entry(&dir).contains("\"is_dir\":true"));
+        if cfg!(unix) {
+            assert!(json_entry(&dir).contains("\"permissions\":493"));
+        }
+
+        let listing = json_listing(&[("a", vec![file]), ("b", vec![dir]), ("c", vec![])], true);
+        assert!(listing.starts_with("{\n  \"a\": [\n    {\"name\":\"main.rs\""));
+        assert!(listing.contains("  ],\n  \"b\": [\n    {\"name\":\"src\""));
+        assert!(listing.ends_with("  \"c\": []\n}"));
+        assert!(!listing.contains('\x1b'));
+        assert!(
+            json_listing(&[(".", vec![entry("x", 1)])], false).starts_with("[\n  {\"name\":\"x\"")
+        );
+        assert_eq!(json_listing(&[], false), "[]");
+    }
+
+    #[test]
+    fn test_json_envelope_carries_the_version() {
+        let listing = json_listing(&[(".", vec![entry("x", 1)])], false);
+        let wrapped = json_envelope(&listing);
+        assert!(wrapped.starts_with("{\n  \"version\": 1,\n  \"entries\": [\n    {\"name\":\"x\""));
+        assert!(wrapped.ends_with("}\n  ]\n}"));
+        assert_eq!(
+            json_envelope("[]"),
+            "{\n  \"version\": 1,\n  \"entries\": []\n}"
+        );
+        assert!(Arg::parse_from(["vw", "--format", "json", "--json-bare"]).json_bare);
+    }
+
+    #[test]
+    fn test_tree_json_matches_tree_shape() {
+        let root = temp_dir("tree-json");
+        std::fs::create_dir_all(root.join("src/empty")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "").unwrap();
+        std::fs::write(root.join("Cargo.toml"), "").unwrap();
+        std::fs::write(root.join(".hidden"), "").unwrap();
+
+        let arg = Arg {
+            recursive: true,
+            tree_json: true,
+            ..default_arg()
         };
-        let formatted = format_entries(entries, &arg);
-        assert!(formatted[0].contains("2.0K"));
+        let root_name = root.to_str().unwrap();
+        let entries = collect_entries(root_name, &arg, &mut Warnings::default()).unwrap();
+        let entries = sort_entries(
+            should_display(entries, &arg),
+            &arg,
+            &mut Warnings::default(),
+        );
+        let expected = [
+            "[".to_string(),
+            format!(
+                "  {{\"type\":\"directory\",\"name\":{},\"entry_count\":2,\"contents\":[",
+                json_string(root_name)
+            ),
+            "    {\"type\":\"file\",\"name\":\"Cargo.toml\"},".to_string(),
+            "    {\"type\":\"directory\",\"name\":\"src\",\"entry_count\":2,\"contents\":["
+                .to_string(),
+            "      {\"type\":\"directory\",\"name\":\"empty\",\"entry_count\":0,\"contents\":["
+                .to_string(),
+            "      ]},".to_string(),
+            "      {\"type\":\"file\",\"name\":\"main.rs\"}".to_string(),
+            "    ]}".to_string(),
+            "  ]}".to_string(),
+            ",".to_string(),
+            "  {\"type\":\"report\",\"directories\":2,\"files\":2}".to_string(),
+            "]".to_string(),
+        ];
+        assert_eq!(tree_json(&[(root_name, entries)]), expected.join("\n"));
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tree_json_entry_count_follows_the_listing() {
+        let root = temp_dir("tree-json-count");
+        std::fs::create_dir(root.join("sub")).unwrap();
+        for name in ["sub/a", "sub/b", "sub/.c", "d"] {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+        let root_name = root.to_str().unwrap();
+        let counts = |flags: &[&str]| {
+            let arg = Arg::parse_from([&["vw", "-R", "--tree-json"], flags].concat());
+            let entries = collect_entries(root_name, &arg, &mut Warnings::default()).unwrap();
+            let entries = sort_entries(
+                should_display(entries, &arg),
+                &arg,
+                &mut Warnings::default(),
+            );
+            let json = tree_json(&[(root_name, entries)]);
+            json.split("\"entry_count\":")
+                .skip(1)
+                .map(|rest| rest.split(',').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        // Only the children that are listed count, so hidden ones come in with -a
+        assert_eq!(counts(&[]), ["2", "2"]);
+        assert_eq!(counts(&["-a"]), ["2", "3"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_tree_json_when_every_root_failed() {
+        assert_eq!(
+            tree_json(&[]),
+            "[\n  {\"type\":\"report\",\"directories\":0,\"files\":0}\n]"
+        );
     }
 }